@@ -183,12 +183,16 @@ pub mod {{mod}} {
             R: {{crate}}::BinderAsyncRuntime + Send + Sync + 'static,
         {
             struct Wrapper<T, R> {
-                _inner: T,
+                _inner: std::sync::Arc<T>,
                 _rt: R,
+                {%- if has_oneway %}
+                _oneway: {{crate}}::OnewayQueue,
+                {%- endif %}
             }
             impl<T, R> {{crate}}::Interface for Wrapper<T, R> where T: {{crate}}::Interface, R: Send + Sync {
                 fn as_binder(&self) -> {{crate}}::SIBinder { self._inner.as_binder() }
                 fn dump(&self, _writer: &mut dyn std::io::Write, _args: &[String]) -> {{crate}}::Result<()> { self._inner.dump(_writer, _args) }
+                fn handle_shell_command(&self, _stdin: &mut dyn std::io::Read, _stdout: &mut dyn std::io::Write, _stderr: &mut dyn std::io::Write, _args: &[String]) -> {{crate}}::Result<()> { self._inner.handle_shell_command(_stdin, _stdout, _stderr, _args) }
             }
             impl<T, R> {{bn_name}}Adapter for Wrapper<T, R>
             where
@@ -199,8 +203,29 @@ pub mod {{mod}} {
                     self
                 }
                 fn as_async(&self) -> &dyn {{name}}AsyncService {
-                    &self._inner
+                    &*self._inner
+                }
+                {%- if has_oneway %}
+                fn handle_oneway(&self, _code: {{crate}}::TransactionCode, _reader: &mut {{crate}}::Parcel) -> {{crate}}::Result<bool> {
+                    match _code {
+                    {%- for member in fn_members %}
+                    {%- if oneway or member.oneway %}
+                        transactions::r#{{ member.identifier }} => {
+                        {%- for decl in member.transaction_decls %}
+                            {{ decl }}
+                        {%- endfor %}
+                            let _inner = std::sync::Arc::clone(&self._inner);
+                            self._oneway.push(&self._rt, "{{ member.identifier }}", Box::pin(async move {
+                                _inner.r#{{ member.identifier }}({{ member.transaction_params }}).await
+                            }));
+                            Ok(true)
+                        }
+                    {%- endif %}
+                    {%- endfor %}
+                        _ => Ok(false),
+                    }
                 }
+                {%- endif %}
             }
             impl<T, R> {{name}} for Wrapper<T, R>
             where
@@ -213,7 +238,11 @@ pub mod {{mod}} {
                 }
                 {%- endfor %}
             }
-            let wrapped = Wrapper { _inner: inner, _rt: rt };
+            {%- if has_oneway %}
+            let wrapped = Wrapper { _inner: std::sync::Arc::new(inner), _rt: rt, _oneway: Default::default() };
+            {%- else %}
+            let wrapped = Wrapper { _inner: std::sync::Arc::new(inner), _rt: rt };
+            {%- endif %}
             let binder = {{crate}}::native::Binder::new_with_stability({{bn_name}}(Box::new(wrapped)), {{crate}}::Stability::default());
             {{crate}}::Strong::new(Box::new(binder))
         }
@@ -603,6 +632,7 @@ impl Generator {
         context.insert("bn_name", &format!("Bn{}", &decl.name[1..]));
         context.insert("bp_name", &format!("Bp{}", &decl.name[1..]));
         context.insert("oneway", &decl.oneway);
+        context.insert("has_oneway", &(decl.oneway || fn_members.iter().any(|member| member.oneway)));
         context.insert("nested", &nested.trim());
         context.insert("enabled_async", &enabled_async);
 
@@ -69,12 +69,13 @@ pub mod ITestService {
             R: rsbinder::BinderAsyncRuntime + Send + Sync + 'static,
         {
             struct Wrapper<T, R> {
-                _inner: T,
+                _inner: std::sync::Arc<T>,
                 _rt: R,
             }
             impl<T, R> rsbinder::Interface for Wrapper<T, R> where T: rsbinder::Interface, R: Send + Sync {
                 fn as_binder(&self) -> rsbinder::SIBinder { self._inner.as_binder() }
                 fn dump(&self, _writer: &mut dyn std::io::Write, _args: &[String]) -> rsbinder::Result<()> { self._inner.dump(_writer, _args) }
+                fn handle_shell_command(&self, _stdin: &mut dyn std::io::Read, _stdout: &mut dyn std::io::Write, _stderr: &mut dyn std::io::Write, _args: &[String]) -> rsbinder::Result<()> { self._inner.handle_shell_command(_stdin, _stdout, _stderr, _args) }
             }
             impl<T, R> BnTestServiceAdapter for Wrapper<T, R>
             where
@@ -85,7 +86,7 @@ pub mod ITestService {
                     self
                 }
                 fn as_async(&self) -> &dyn ITestServiceAsyncService {
-                    &self._inner
+                    &*self._inner
                 }
             }
             impl<T, R> ITestService for Wrapper<T, R>
@@ -103,7 +104,7 @@ pub mod ITestService {
                     self._rt.block_on(self._inner.r#FillOutStructuredParcelable(_arg_parcel))
                 }
             }
-            let wrapped = Wrapper { _inner: inner, _rt: rt };
+            let wrapped = Wrapper { _inner: std::sync::Arc::new(inner), _rt: rt };
             let binder = rsbinder::native::Binder::new_with_stability(BnTestService(Box::new(wrapped)), rsbinder::Stability::default());
             rsbinder::Strong::new(Box::new(binder))
         }
@@ -433,12 +434,13 @@ pub mod FixedSizeArrayExample {
                 R: rsbinder::BinderAsyncRuntime + Send + Sync + 'static,
             {
                 struct Wrapper<T, R> {
-                    _inner: T,
+                    _inner: std::sync::Arc<T>,
                     _rt: R,
                 }
                 impl<T, R> rsbinder::Interface for Wrapper<T, R> where T: rsbinder::Interface, R: Send + Sync {
                     fn as_binder(&self) -> rsbinder::SIBinder { self._inner.as_binder() }
                     fn dump(&self, _writer: &mut dyn std::io::Write, _args: &[String]) -> rsbinder::Result<()> { self._inner.dump(_writer, _args) }
+                    fn handle_shell_command(&self, _stdin: &mut dyn std::io::Read, _stdout: &mut dyn std::io::Write, _stderr: &mut dyn std::io::Write, _args: &[String]) -> rsbinder::Result<()> { self._inner.handle_shell_command(_stdin, _stdout, _stderr, _args) }
                 }
                 impl<T, R> BnRepeatFixedSizeArrayAdapter for Wrapper<T, R>
                 where
@@ -449,7 +451,7 @@ pub mod FixedSizeArrayExample {
                         self
                     }
                     fn as_async(&self) -> &dyn IRepeatFixedSizeArrayAsyncService {
-                        &self._inner
+                        &*self._inner
                     }
                 }
                 impl<T, R> IRepeatFixedSizeArray for Wrapper<T, R>
@@ -461,7 +463,7 @@ pub mod FixedSizeArrayExample {
                         self._rt.block_on(self._inner.r#Repeat2dParcelables(_arg_input, _arg_repeated))
                     }
                 }
-                let wrapped = Wrapper { _inner: inner, _rt: rt };
+                let wrapped = Wrapper { _inner: std::sync::Arc::new(inner), _rt: rt };
                 let binder = rsbinder::native::Binder::new_with_stability(BnRepeatFixedSizeArray(Box::new(wrapped)), rsbinder::Stability::default());
                 rsbinder::Strong::new(Box::new(binder))
             }
@@ -634,12 +636,13 @@ pub mod FixedSizeArrayExample {
                 R: rsbinder::BinderAsyncRuntime + Send + Sync + 'static,
             {
                 struct Wrapper<T, R> {
-                    _inner: T,
+                    _inner: std::sync::Arc<T>,
                     _rt: R,
                 }
                 impl<T, R> rsbinder::Interface for Wrapper<T, R> where T: rsbinder::Interface, R: Send + Sync {
                     fn as_binder(&self) -> rsbinder::SIBinder { self._inner.as_binder() }
                     fn dump(&self, _writer: &mut dyn std::io::Write, _args: &[String]) -> rsbinder::Result<()> { self._inner.dump(_writer, _args) }
+                    fn handle_shell_command(&self, _stdin: &mut dyn std::io::Read, _stdout: &mut dyn std::io::Write, _stderr: &mut dyn std::io::Write, _args: &[String]) -> rsbinder::Result<()> { self._inner.handle_shell_command(_stdin, _stdout, _stderr, _args) }
                 }
                 impl<T, R> BnEmptyInterfaceAdapter for Wrapper<T, R>
                 where
@@ -650,7 +653,7 @@ pub mod FixedSizeArrayExample {
                         self
                     }
                     fn as_async(&self) -> &dyn IEmptyInterfaceAsyncService {
-                        &self._inner
+                        &*self._inner
                     }
                 }
                 impl<T, R> IEmptyInterface for Wrapper<T, R>
@@ -659,7 +662,7 @@ pub mod FixedSizeArrayExample {
                     R: rsbinder::BinderAsyncRuntime + Send + Sync + 'static,
                 {
                 }
-                let wrapped = Wrapper { _inner: inner, _rt: rt };
+                let wrapped = Wrapper { _inner: std::sync::Arc::new(inner), _rt: rt };
                 let binder = rsbinder::native::Binder::new_with_stability(BnEmptyInterface(Box::new(wrapped)), rsbinder::Stability::default());
                 rsbinder::Strong::new(Box::new(binder))
             }
@@ -778,12 +781,13 @@ pub mod ArrayOfInterfaces {
                 R: rsbinder::BinderAsyncRuntime + Send + Sync + 'static,
             {
                 struct Wrapper<T, R> {
-                    _inner: T,
+                    _inner: std::sync::Arc<T>,
                     _rt: R,
                 }
                 impl<T, R> rsbinder::Interface for Wrapper<T, R> where T: rsbinder::Interface, R: Send + Sync {
                     fn as_binder(&self) -> rsbinder::SIBinder { self._inner.as_binder() }
                     fn dump(&self, _writer: &mut dyn std::io::Write, _args: &[String]) -> rsbinder::Result<()> { self._inner.dump(_writer, _args) }
+                    fn handle_shell_command(&self, _stdin: &mut dyn std::io::Read, _stdout: &mut dyn std::io::Write, _stderr: &mut dyn std::io::Write, _args: &[String]) -> rsbinder::Result<()> { self._inner.handle_shell_command(_stdin, _stdout, _stderr, _args) }
                 }
                 impl<T, R> BnEmptyInterfaceAdapter for Wrapper<T, R>
                 where
@@ -794,7 +798,7 @@ pub mod ArrayOfInterfaces {
                         self
                     }
                     fn as_async(&self) -> &dyn IEmptyInterfaceAsyncService {
-                        &self._inner
+                        &*self._inner
                     }
                 }
                 impl<T, R> IEmptyInterface for Wrapper<T, R>
@@ -803,7 +807,7 @@ pub mod ArrayOfInterfaces {
                     R: rsbinder::BinderAsyncRuntime + Send + Sync + 'static,
                 {
                 }
-                let wrapped = Wrapper { _inner: inner, _rt: rt };
+                let wrapped = Wrapper { _inner: std::sync::Arc::new(inner), _rt: rt };
                 let binder = rsbinder::native::Binder::new_with_stability(BnEmptyInterface(Box::new(wrapped)), rsbinder::Stability::default());
                 rsbinder::Strong::new(Box::new(binder))
             }
@@ -875,12 +879,13 @@ pub mod ArrayOfInterfaces {
                 R: rsbinder::BinderAsyncRuntime + Send + Sync + 'static,
             {
                 struct Wrapper<T, R> {
-                    _inner: T,
+                    _inner: std::sync::Arc<T>,
                     _rt: R,
                 }
                 impl<T, R> rsbinder::Interface for Wrapper<T, R> where T: rsbinder::Interface, R: Send + Sync {
                     fn as_binder(&self) -> rsbinder::SIBinder { self._inner.as_binder() }
                     fn dump(&self, _writer: &mut dyn std::io::Write, _args: &[String]) -> rsbinder::Result<()> { self._inner.dump(_writer, _args) }
+                    fn handle_shell_command(&self, _stdin: &mut dyn std::io::Read, _stdout: &mut dyn std::io::Write, _stderr: &mut dyn std::io::Write, _args: &[String]) -> rsbinder::Result<()> { self._inner.handle_shell_command(_stdin, _stdout, _stderr, _args) }
                 }
                 impl<T, R> BnMyInterfaceAdapter for Wrapper<T, R>
                 where
@@ -891,7 +896,7 @@ pub mod ArrayOfInterfaces {
                         self
                     }
                     fn as_async(&self) -> &dyn IMyInterfaceAsyncService {
-                        &self._inner
+                        &*self._inner
                     }
                 }
                 impl<T, R> IMyInterface for Wrapper<T, R>
@@ -903,7 +908,7 @@ pub mod ArrayOfInterfaces {
                         self._rt.block_on(self._inner.r#methodWithInterfaces(_arg_iface, _arg_nullable_iface, _arg_iface_array_in, _arg_iface_array_out, _arg_iface_array_inout, _arg_nullable_iface_array_in, _arg_nullable_iface_array_out, _arg_nullable_iface_array_inout))
                     }
                 }
-                let wrapped = Wrapper { _inner: inner, _rt: rt };
+                let wrapped = Wrapper { _inner: std::sync::Arc::new(inner), _rt: rt };
                 let binder = rsbinder::native::Binder::new_with_stability(BnMyInterface(Box::new(wrapped)), rsbinder::Stability::default());
                 rsbinder::Strong::new(Box::new(binder))
             }
@@ -1499,12 +1504,13 @@ pub mod ITestService {
             R: rsbinder::BinderAsyncRuntime + Send + Sync + 'static,
         {
             struct Wrapper<T, R> {
-                _inner: T,
+                _inner: std::sync::Arc<T>,
                 _rt: R,
             }
             impl<T, R> rsbinder::Interface for Wrapper<T, R> where T: rsbinder::Interface, R: Send + Sync {
                 fn as_binder(&self) -> rsbinder::SIBinder { self._inner.as_binder() }
                 fn dump(&self, _writer: &mut dyn std::io::Write, _args: &[String]) -> rsbinder::Result<()> { self._inner.dump(_writer, _args) }
+                fn handle_shell_command(&self, _stdin: &mut dyn std::io::Read, _stdout: &mut dyn std::io::Write, _stderr: &mut dyn std::io::Write, _args: &[String]) -> rsbinder::Result<()> { self._inner.handle_shell_command(_stdin, _stdout, _stderr, _args) }
             }
             impl<T, R> BnTestServiceAdapter for Wrapper<T, R>
             where
@@ -1515,7 +1521,7 @@ pub mod ITestService {
                     self
                 }
                 fn as_async(&self) -> &dyn ITestServiceAsyncService {
-                    &self._inner
+                    &*self._inner
                 }
             }
             impl<T, R> ITestService for Wrapper<T, R>
@@ -1527,7 +1533,7 @@ pub mod ITestService {
                     self._rt.block_on(self._inner.r#RepeatByteEnum(_arg_token))
                 }
             }
-            let wrapped = Wrapper { _inner: inner, _rt: rt };
+            let wrapped = Wrapper { _inner: std::sync::Arc::new(inner), _rt: rt };
             let binder = rsbinder::native::Binder::new_with_stability(BnTestService(Box::new(wrapped)), rsbinder::Stability::default());
             rsbinder::Strong::new(Box::new(binder))
         }
@@ -2254,4 +2260,236 @@ pub mod Union {
 }
         "#)?;
     Ok(())
-}
\ No newline at end of file
+}
+
+#[test]
+fn test_oneway_async_service() -> Result<(), Box<dyn Error>> {
+    aidl_generator(r##"
+package android.aidl.tests;
+interface IOnewayTest {
+    oneway void Send(int value);
+    int Get();
+}
+        "##,
+        r##"
+pub mod IOnewayTest {
+    #![allow(non_upper_case_globals, non_snake_case, dead_code)]
+    pub trait IOnewayTest: rsbinder::Interface + Send {
+        fn descriptor() -> &'static str where Self: Sized { "android.aidl.tests.IOnewayTest" }
+        fn r#Send(&self, _arg_value: i32) -> rsbinder::status::Result<()>;
+        fn r#Get(&self) -> rsbinder::status::Result<i32>;
+        fn getDefaultImpl() -> IOnewayTestDefaultRef where Self: Sized {
+            DEFAULT_IMPL.lock().unwrap().clone()
+        }
+        fn setDefaultImpl(d: IOnewayTestDefaultRef) -> IOnewayTestDefaultRef where Self: Sized {
+            std::mem::replace(&mut *DEFAULT_IMPL.lock().unwrap(), d)
+        }
+    }
+    pub trait IOnewayTestAsync<P>: rsbinder::Interface + Send {
+        fn descriptor() -> &'static str where Self: Sized { "android.aidl.tests.IOnewayTest" }
+        fn r#Send<'a>(&'a self, _arg_value: i32) -> rsbinder::BoxFuture<'a, rsbinder::status::Result<()>>;
+        fn r#Get<'a>(&'a self) -> rsbinder::BoxFuture<'a, rsbinder::status::Result<i32>>;
+    }
+    #[::async_trait::async_trait]
+    pub trait IOnewayTestAsyncService: rsbinder::Interface + Send {
+        fn descriptor() -> &'static str where Self: Sized { "android.aidl.tests.IOnewayTest" }
+        async fn r#Send(&self, _arg_value: i32) -> rsbinder::status::Result<()>;
+        async fn r#Get(&self) -> rsbinder::status::Result<i32>;
+    }
+    impl BnOnewayTest
+    {
+        pub fn new_async_binder<T, R>(inner: T, rt: R) -> rsbinder::Strong<dyn IOnewayTest>
+        where
+            T: IOnewayTestAsyncService + Sync + Send + 'static,
+            R: rsbinder::BinderAsyncRuntime + Send + Sync + 'static,
+        {
+            struct Wrapper<T, R> {
+                _inner: std::sync::Arc<T>,
+                _rt: R,
+                _oneway: rsbinder::OnewayQueue,
+            }
+            impl<T, R> rsbinder::Interface for Wrapper<T, R> where T: rsbinder::Interface, R: Send + Sync {
+                fn as_binder(&self) -> rsbinder::SIBinder { self._inner.as_binder() }
+                fn dump(&self, _writer: &mut dyn std::io::Write, _args: &[String]) -> rsbinder::Result<()> { self._inner.dump(_writer, _args) }
+                fn handle_shell_command(&self, _stdin: &mut dyn std::io::Read, _stdout: &mut dyn std::io::Write, _stderr: &mut dyn std::io::Write, _args: &[String]) -> rsbinder::Result<()> { self._inner.handle_shell_command(_stdin, _stdout, _stderr, _args) }
+            }
+            impl<T, R> BnOnewayTestAdapter for Wrapper<T, R>
+            where
+                T: IOnewayTestAsyncService + Sync + Send + 'static,
+                R: rsbinder::BinderAsyncRuntime + Send + Sync + 'static,
+            {
+                fn as_sync(&self) -> &dyn IOnewayTest {
+                    self
+                }
+                fn as_async(&self) -> &dyn IOnewayTestAsyncService {
+                    &*self._inner
+                }
+                fn handle_oneway(&self, _code: rsbinder::TransactionCode, _reader: &mut rsbinder::Parcel) -> rsbinder::Result<bool> {
+                    match _code {
+                        transactions::r#Send => {
+                            let _arg_value: i32 = _reader.read()?;
+                            let _inner = std::sync::Arc::clone(&self._inner);
+                            self._oneway.push(&self._rt, "Send", Box::pin(async move {
+                                _inner.r#Send(_arg_value).await
+                            }));
+                            Ok(true)
+                        }
+                        _ => Ok(false),
+                    }
+                }
+            }
+            impl<T, R> IOnewayTest for Wrapper<T, R>
+            where
+                T: IOnewayTestAsyncService + Sync + Send + 'static,
+                R: rsbinder::BinderAsyncRuntime + Send + Sync + 'static,
+            {
+                fn r#Send(&self, _arg_value: i32) -> rsbinder::status::Result<()> {
+                    self._rt.block_on(self._inner.r#Send(_arg_value))
+                }
+                fn r#Get(&self) -> rsbinder::status::Result<i32> {
+                    self._rt.block_on(self._inner.r#Get())
+                }
+            }
+            let wrapped = Wrapper { _inner: std::sync::Arc::new(inner), _rt: rt, _oneway: Default::default() };
+            let binder = rsbinder::native::Binder::new_with_stability(BnOnewayTest(Box::new(wrapped)), rsbinder::Stability::default());
+            rsbinder::Strong::new(Box::new(binder))
+        }
+    }
+    pub trait IOnewayTestDefault: Send + Sync {
+        fn r#Send(&self, _arg_value: i32) -> rsbinder::status::Result<()> {
+            Err(rsbinder::StatusCode::UnknownTransaction.into())
+        }
+        fn r#Get(&self) -> rsbinder::status::Result<i32> {
+            Err(rsbinder::StatusCode::UnknownTransaction.into())
+        }
+    }
+    pub(crate) mod transactions {
+        pub(crate) const r#Send: rsbinder::TransactionCode = rsbinder::FIRST_CALL_TRANSACTION + 0;
+        pub(crate) const r#Get: rsbinder::TransactionCode = rsbinder::FIRST_CALL_TRANSACTION + 1;
+    }
+    pub type IOnewayTestDefaultRef = Option<std::sync::Arc<dyn IOnewayTestDefault>>;
+    use lazy_static::lazy_static;
+    lazy_static! {
+        static ref DEFAULT_IMPL: std::sync::Mutex<IOnewayTestDefaultRef> = std::sync::Mutex::new(None);
+    }
+    rsbinder::declare_binder_interface! {
+        IOnewayTest["android.aidl.tests.IOnewayTest"] {
+            native: {
+                BnOnewayTest(on_transact),
+                adapter: BnOnewayTestAdapter,
+                r#async: IOnewayTestAsyncService,
+            },
+            proxy: BpOnewayTest,
+            r#async: IOnewayTestAsync,
+        }
+    }
+    impl BpOnewayTest {
+        fn build_parcel_Send(&self, _arg_value: i32) -> rsbinder::Result<rsbinder::Parcel> {
+            let mut data = self.binder.as_proxy().unwrap().prepare_transact(true)?;
+            data.write(&_arg_value)?;
+            Ok(data)
+        }
+        fn read_response_Send(&self, _arg_value: i32, _aidl_reply: rsbinder::Result<Option<rsbinder::Parcel>>) -> rsbinder::status::Result<()> {
+            Ok(())
+        }
+        fn build_parcel_Get(&self) -> rsbinder::Result<rsbinder::Parcel> {
+            let data = self.binder.as_proxy().unwrap().prepare_transact(true)?;
+            Ok(data)
+        }
+        fn read_response_Get(&self, _aidl_reply: rsbinder::Result<Option<rsbinder::Parcel>>) -> rsbinder::status::Result<i32> {
+            if let Err(rsbinder::StatusCode::UnknownTransaction) = _aidl_reply {
+                if let Some(_aidl_default_impl) = <Self as IOnewayTest>::getDefaultImpl() {
+                  return _aidl_default_impl.r#Get();
+                }
+            }
+            let mut _aidl_reply = _aidl_reply?.ok_or(rsbinder::StatusCode::UnexpectedNull)?;
+            let _status = _aidl_reply.read::<rsbinder::Status>()?;
+            if !_status.is_ok() { return Err(_status); }
+            let _aidl_return: i32 = _aidl_reply.read()?;
+            Ok(_aidl_return)
+        }
+    }
+    impl IOnewayTest for BpOnewayTest {
+        fn r#Send(&self, _arg_value: i32) -> rsbinder::status::Result<()> {
+            let _aidl_data = self.build_parcel_Send(_arg_value)?;
+            let _aidl_reply = self.binder.as_proxy().unwrap().submit_transact(transactions::r#Send, &_aidl_data, rsbinder::FLAG_ONEWAY | rsbinder::FLAG_CLEAR_BUF);
+            self.read_response_Send(_arg_value, _aidl_reply)
+        }
+        fn r#Get(&self) -> rsbinder::status::Result<i32> {
+            let _aidl_data = self.build_parcel_Get()?;
+            let _aidl_reply = self.binder.as_proxy().unwrap().submit_transact(transactions::r#Get, &_aidl_data, rsbinder::FLAG_CLEAR_BUF);
+            self.read_response_Get(_aidl_reply)
+        }
+    }
+    impl<P: rsbinder::BinderAsyncPool> IOnewayTestAsync<P> for BpOnewayTest {
+        fn r#Send<'a>(&'a self, _arg_value: i32) -> rsbinder::BoxFuture<'a, rsbinder::status::Result<()>> {
+            let _aidl_data = match self.build_parcel_Send(_arg_value) {
+                Ok(_aidl_data) => _aidl_data,
+                Err(err) => return Box::pin(std::future::ready(Err(err.into()))),
+            };
+            let binder = self.binder.clone();
+            P::spawn(
+                move || binder.as_proxy().unwrap().submit_transact(transactions::r#Send, &_aidl_data, rsbinder::FLAG_CLEAR_BUF | rsbinder::FLAG_PRIVATE_LOCAL),
+                move |_aidl_reply| async move {
+                    self.read_response_Send(_arg_value, _aidl_reply)
+                }
+            )
+        }
+        fn r#Get<'a>(&'a self) -> rsbinder::BoxFuture<'a, rsbinder::status::Result<i32>> {
+            let _aidl_data = match self.build_parcel_Get() {
+                Ok(_aidl_data) => _aidl_data,
+                Err(err) => return Box::pin(std::future::ready(Err(err.into()))),
+            };
+            let binder = self.binder.clone();
+            P::spawn(
+                move || binder.as_proxy().unwrap().submit_transact(transactions::r#Get, &_aidl_data, rsbinder::FLAG_CLEAR_BUF | rsbinder::FLAG_PRIVATE_LOCAL),
+                move |_aidl_reply| async move {
+                    self.read_response_Get(_aidl_reply)
+                }
+            )
+        }
+    }
+    impl<P: rsbinder::BinderAsyncPool> IOnewayTestAsync<P> for rsbinder::Binder<BnOnewayTest>
+    {
+        fn r#Send<'a>(&'a self, _arg_value: i32) -> rsbinder::BoxFuture<'a, rsbinder::status::Result<()>> {
+            self.0.as_async().r#Send(_arg_value)
+        }
+        fn r#Get<'a>(&'a self) -> rsbinder::BoxFuture<'a, rsbinder::status::Result<i32>> {
+            self.0.as_async().r#Get()
+        }
+    }
+    impl IOnewayTest for rsbinder::Binder<BnOnewayTest> {
+        fn r#Send(&self, _arg_value: i32) -> rsbinder::status::Result<()> {
+            self.0.as_sync().r#Send(_arg_value)
+        }
+        fn r#Get(&self) -> rsbinder::status::Result<i32> {
+            self.0.as_sync().r#Get()
+        }
+    }
+    fn on_transact(
+        _service: &dyn IOnewayTest, _code: rsbinder::TransactionCode, _reader: &mut rsbinder::Parcel, _reply: &mut rsbinder::Parcel) -> rsbinder::Result<()> {
+        match _code {
+            transactions::r#Send => {
+                let _arg_value: i32 = _reader.read()?;
+                let _aidl_return = _service.r#Send(_arg_value);
+                Ok(())
+            }
+            transactions::r#Get => {
+                let _aidl_return = _service.r#Get();
+                match &_aidl_return {
+                    Ok(_aidl_return) => {
+                        _reply.write(&rsbinder::Status::from(rsbinder::StatusCode::Ok))?;
+                        _reply.write(_aidl_return)?;
+                    }
+                    Err(_aidl_status) => {
+                        _reply.write(_aidl_status)?;
+                    }
+                }
+                Ok(())
+            }
+            _ => Err(rsbinder::StatusCode::UnknownTransaction),
+        }
+    }
+}
+        "##)
+}
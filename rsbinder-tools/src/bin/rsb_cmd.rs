@@ -0,0 +1,94 @@
+// Copyright 2022 Jeff Kim <hiking90@gmail.com>
+// SPDX-License-Identifier: Apache-2.0
+
+use std::sync::Mutex;
+use std::sync::mpsc::{channel, Sender, Receiver, RecvTimeoutError};
+use std::time::Duration;
+
+use rsbinder::*;
+
+// The result is sent before the shell command transaction returns, so it shouldn't take long.
+const RESULT_TIMEOUT: Duration = Duration::from_secs(5);
+
+// The local IResultReceiver which receives the result of the shell command.
+struct ResultReceiver(Mutex<Sender<i32>>);
+
+impl Remotable for ResultReceiver {
+    fn descriptor() -> &'static str {
+        RESULT_RECEIVER_DESCRIPTOR
+    }
+
+    fn on_transact(&self, code: TransactionCode, reader: &mut Parcel, _reply: &mut Parcel) -> Result<()> {
+        match code {
+            FIRST_CALL_TRANSACTION => {
+                let result = reader.read::<i32>()?;
+                self.0.lock().unwrap().send(result).map_err(|_| StatusCode::DeadObject)
+            }
+            _ => Err(StatusCode::UnknownTransaction),
+        }
+    }
+
+    fn on_dump(&self, _writer: &mut dyn std::io::Write, _args: &[String]) -> Result<()> {
+        Ok(())
+    }
+}
+
+fn result_receiver() -> (Binder<ResultReceiver>, Receiver<i32>) {
+    let (sender, receiver) = channel();
+    (Binder::new(ResultReceiver(Mutex::new(sender))), receiver)
+}
+
+fn main() -> std::result::Result<(), Box<dyn std::error::Error>> {
+    let args: Vec<String> = std::env::args().collect();
+
+    if args.len() < 2 {
+        println!("rsb_cmd - Run a shell command of a binder service");
+        println!("Usage: rsb_cmd -l");
+        println!("       rsb_cmd <service_name> [args...]");
+        return Ok(())
+    }
+
+    env_logger::init();
+
+    ProcessState::init(DEFAULT_BINDER_PATH, 0);
+
+    if args[1] == "-l" {
+        println!("Currently running services:");
+        for info in hub::get_service_debug_info() {
+            println!("  {} (pid {})", info.name, info.debugPid);
+        }
+        return Ok(())
+    }
+
+    let service = hub::get_service(&args[1])
+        .ok_or_else(|| format!("Can't find service: {}", args[1]))?;
+    let proxy = service.as_proxy()
+        .ok_or_else(|| format!("Service {} is not a remote binder", args[1]))?;
+
+    // The result is sent back by a oneway transaction, so a binder thread is required.
+    ProcessState::start_thread_pool();
+
+    let (binder, receiver) = result_receiver();
+    proxy.shell_command(std::io::stdin(), std::io::stdout(), std::io::stderr(),
+        &args[2..], Some(&binder.as_binder()))?;
+    // The sender is dropped with the receiver binder if the service released it without the result.
+    drop(binder);
+
+    let result = match receiver.recv_timeout(RESULT_TIMEOUT) {
+        Ok(result) => result,
+        Err(RecvTimeoutError::Timeout) => {
+            eprintln!("{}: timed out waiting for the result", args[1]);
+            std::process::exit(255);
+        }
+        Err(RecvTimeoutError::Disconnected) => {
+            eprintln!("{}: the service didn't send the result", args[1]);
+            std::process::exit(255);
+        }
+    };
+    if result != 0 {
+        eprintln!("{}: {}", args[1], StatusCode::from(result));
+        std::process::exit(255);
+    }
+
+    Ok(())
+}
@@ -0,0 +1,299 @@
+// Copyright 2022 Jeff Kim <hiking90@gmail.com>
+// SPDX-License-Identifier: Apache-2.0
+
+//! The access control policy of the service registrations and lookups.
+//!
+//! Each line of the policy allows an operation on the services matching a name to
+//! the listed principals. A name ending with `*` matches the names with the prefix,
+//! and the rule with the longest name matching a service applies to it.
+//!
+//! ```text
+//! # <operation> <name> <principal>...
+//! add  my.hello  uid:1000
+//! find my.*      gid:audio ctx:u:r:hello_client:s0
+//! find *         any
+//! list *         uid:0
+//! ```
+//!
+//! The operations are `add`, `find` and `list`. A principal is `uid:<user>`, `gid:<group>`,
+//! `ctx:<security context>` or `any`. Without a matching rule, the operation is denied.
+//!
+//! The groups of a caller are read from `/proc/<pid>/status` of the calling pid. The pid
+//! may be reused by another process if the caller exits before the check, and it is 0 for
+//! oneway calls, so `gid:` rules never allow them. Prefer `uid:` and `ctx:` principals for
+//! the services called by oneway transactions or by short-lived processes.
+
+use std::path::PathBuf;
+use std::sync::{RwLock, RwLockReadGuard};
+
+use rsbinder::CallingContext;
+
+use crate::config::{self, ConfigFile, parse_group, parse_user};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operation {
+    /// Register a service, its client callback or unregister it.
+    Add,
+    /// Look up a service or its declaration.
+    Find,
+    /// List a service by listServices().
+    List,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Principal {
+    Uid(u32),
+    Gid(u32),
+    Context(String),
+    Any,
+}
+
+#[derive(Debug)]
+struct Rule {
+    operation: Operation,
+    name: String,
+    principals: Vec<Principal>,
+}
+
+impl Rule {
+    // The length of the matched name, or None if the rule doesn't match `name`.
+    fn matches(&self, operation: Operation, name: &str) -> Option<usize> {
+        if self.operation != operation {
+            return None;
+        }
+        match self.name.strip_suffix('*') {
+            Some(prefix) if name.starts_with(prefix) => Some(prefix.len()),
+            // An exact match is preferred to the prefix of the same length.
+            None if self.name == name => Some(name.len() + 1),
+            _ => None,
+        }
+    }
+}
+
+/// The identity of a caller checked by the policy.
+#[derive(Debug, Default)]
+pub struct Caller {
+    pub uid: u32,
+    pub gids: Vec<u32>,
+    pub sid: Option<String>,
+}
+
+impl Caller {
+    // The gids are empty if the process of the pid is gone or the pid is 0 for oneway calls.
+    fn from_context(context: &CallingContext) -> Self {
+        Caller {
+            uid: context.uid,
+            gids: process_gids(context.pid),
+            sid: context.sid.clone(),
+        }
+    }
+}
+
+// The effective and supplementary group ids of a process.
+fn process_gids(pid: i32) -> Vec<u32> {
+    let Ok(status) = std::fs::read_to_string(format!("/proc/{}/status", pid)) else {
+        return Vec::new();
+    };
+
+    let mut gids = Vec::new();
+    for line in status.lines() {
+        if let Some(value) = line.strip_prefix("Gid:") {
+            // Real, effective, saved set and filesystem gids.
+            gids.extend(value.split_whitespace().nth(1).and_then(|gid| gid.parse::<u32>().ok()));
+        } else if let Some(value) = line.strip_prefix("Groups:") {
+            gids.extend(value.split_whitespace().filter_map(|gid| gid.parse::<u32>().ok()));
+        }
+    }
+    gids
+}
+
+#[derive(Debug, Default)]
+pub struct Policy {
+    rules: Vec<Rule>,
+}
+
+impl ConfigFile for Policy {
+    fn parse(text: &str) -> Result<Self, String> {
+        let mut rules = Vec::new();
+
+        for line in config::lines(text) {
+            let [operation, name, principals @ ..] = line.tokens.as_slice() else {
+                return Err(line.error("a rule requires an operation and a name"));
+            };
+
+            let operation = match *operation {
+                "add" => Operation::Add,
+                "find" => Operation::Find,
+                "list" => Operation::List,
+                _ => return Err(line.error(format!("unknown operation {}", operation))),
+            };
+            if name.find('*').is_some_and(|pos| pos != name.len() - 1) {
+                return Err(line.error(format!("* must be at the end of {}", name)));
+            }
+
+            let principals = principals.iter()
+                .map(|principal| parse_principal(principal).map_err(|e| line.error(e)))
+                .collect::<Result<Vec<_>, _>>()?;
+
+            rules.push(Rule {
+                operation,
+                name: name.to_string(),
+                principals,
+            });
+        }
+
+        Ok(Policy { rules })
+    }
+}
+
+impl Policy {
+    pub fn is_allowed(&self, operation: Operation, name: &str, caller: &Caller) -> bool {
+        let rule = self.rules.iter()
+            .filter_map(|rule| rule.matches(operation, name).map(|len| (len, rule)))
+            .max_by_key(|(len, _)| *len);
+
+        let Some((_, rule)) = rule else {
+            return false;
+        };
+
+        rule.principals.iter().any(|principal| match principal {
+            Principal::Uid(uid) => caller.uid == *uid,
+            Principal::Gid(gid) => caller.gids.contains(gid),
+            Principal::Context(context) => caller.sid.as_deref() == Some(context.as_str()),
+            Principal::Any => true,
+        })
+    }
+}
+
+fn parse_principal(principal: &str) -> Result<Principal, String> {
+    if principal == "any" {
+        return Ok(Principal::Any);
+    }
+
+    match principal.split_once(':') {
+        Some(("uid", user)) => parse_user(user).map(Principal::Uid),
+        Some(("gid", group)) => parse_group(group).map(Principal::Gid),
+        Some(("ctx", context)) if !context.is_empty() => Ok(Principal::Context(context.to_owned())),
+        _ => Err(format!("unknown principal {}", principal)),
+    }
+}
+
+/// The access control of rsb_hub. Everything is allowed without a policy file.
+pub struct AccessControl {
+    path: Option<PathBuf>,
+    policy: RwLock<Option<Policy>>,
+    // rsb_hub itself is always allowed.
+    self_uid: u32,
+}
+
+impl AccessControl {
+    pub fn new(path: Option<PathBuf>) -> Result<Self, String> {
+        let policy = path.as_deref().map(Policy::load).transpose()?;
+        Ok(Self {
+            path,
+            policy: RwLock::new(policy),
+            self_uid: nix::unistd::geteuid().as_raw(),
+        })
+    }
+
+    /// Load the policy file again. The current policy is kept if it fails.
+    pub fn reload(&self) {
+        let Some(path) = &self.path else {
+            return;
+        };
+        match Policy::load(path) {
+            Ok(policy) => {
+                *self.policy.write().unwrap() = Some(policy);
+                log::info!("Reloaded the access control policy {}", path.display());
+            }
+            Err(err) => log::error!("Failed to reload the access control policy: {}", err),
+        }
+    }
+
+    /// Check the caller of the current transaction.
+    pub fn check(&self, operation: Operation, name: &str) -> rsbinder::status::Result<()> {
+        if self.checker().can(operation, name) {
+            Ok(())
+        } else {
+            Err((rsbinder::ExceptionCode::Security, "Permission denied").into())
+        }
+    }
+
+    /// Check the caller for the name of each item, e.g. to filter the services listed to the caller.
+    pub fn filter<T>(&self, operation: Operation, mut items: Vec<T>, name: impl Fn(&T) -> &str) -> Vec<T> {
+        let checker = self.checker();
+        items.retain(|item| checker.can(operation, name(item)));
+        items
+    }
+
+    // The caller is built once for all the checks of a transaction.
+    fn checker(&self) -> Checker<'_> {
+        let policy = self.policy.read().unwrap();
+        let context = rsbinder::calling_context();
+        let caller = (policy.is_some() && context.uid != self.self_uid)
+            .then(|| Caller::from_context(&context));
+        Checker { policy, context, caller }
+    }
+}
+
+struct Checker<'a> {
+    policy: RwLockReadGuard<'a, Option<Policy>>,
+    context: CallingContext,
+    // None if everything is allowed to the caller.
+    caller: Option<Caller>,
+}
+
+impl Checker<'_> {
+    fn can(&self, operation: Operation, name: &str) -> bool {
+        let (Some(policy), Some(caller)) = (self.policy.as_ref(), &self.caller) else {
+            return true;
+        };
+
+        let allowed = policy.is_allowed(operation, name, caller);
+        if !allowed {
+            log::warn!("{:?} {} is denied to pid {} uid {} ctx {:?}",
+                operation, name, self.context.pid, self.context.uid, self.context.sid);
+        }
+        allowed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_policy() {
+        let policy = Policy::parse(r#"
+            # Only uid 1000 can register my.hello.
+            add  my.hello  uid:1000
+            find my.*      gid:20 ctx:u:r:client:s0
+            find my.secret uid:0
+            list *         any
+        "#).unwrap();
+
+        let user = Caller { uid: 1000, gids: vec![1000], sid: None };
+        let member = Caller { uid: 1001, gids: vec![1001, 20], sid: None };
+        let labeled = Caller { uid: 1002, gids: vec![], sid: Some("u:r:client:s0".to_owned()) };
+        let root = Caller { uid: 0, ..Default::default() };
+
+        assert!(policy.is_allowed(Operation::Add, "my.hello", &user));
+        assert!(!policy.is_allowed(Operation::Add, "my.hello", &member));
+        assert!(!policy.is_allowed(Operation::Add, "my.other", &user));
+
+        assert!(policy.is_allowed(Operation::Find, "my.hello", &member));
+        assert!(policy.is_allowed(Operation::Find, "my.hello", &labeled));
+        assert!(!policy.is_allowed(Operation::Find, "my.hello", &user));
+        // The exact rule is preferred to the prefix.
+        assert!(!policy.is_allowed(Operation::Find, "my.secret", &member));
+        assert!(policy.is_allowed(Operation::Find, "my.secret", &root));
+        assert!(!policy.is_allowed(Operation::Find, "other.hello", &root));
+
+        assert!(policy.is_allowed(Operation::List, "other.hello", &user));
+
+        assert!(Policy::parse("add").is_err());
+        assert!(Policy::parse("remove my.hello any").is_err());
+        assert!(Policy::parse("add my.*.hello any").is_err());
+        assert!(Policy::parse("add my.hello pid:1").is_err());
+    }
+}
@@ -0,0 +1,73 @@
+// Copyright 2022 Jeff Kim <hiking90@gmail.com>
+// SPDX-License-Identifier: Apache-2.0
+
+//! Tracking of the clients of the services with client callbacks.
+
+/// Whether a service has clients, as notified to its client callbacks.
+#[derive(Debug, Default)]
+pub struct ClientTracker {
+    pub has_clients: bool,
+    // A client got the service, but the kernel may not report it yet.
+    pub guarentee_client: bool,
+}
+
+impl ClientTracker {
+    /// Update the state by the clients reported by the kernel, and return the values of
+    /// onClients() to send to the client callbacks in order.
+    pub fn update(&mut self, name: &str, has_kernel_reported_clients: bool, is_called_on_interval: bool) -> Vec<bool> {
+        let mut notifications = Vec::new();
+
+        if self.guarentee_client {
+            if !self.has_clients && !has_kernel_reported_clients {
+                log::info!("Notifying {} that the service is guaranteed to be in use", name);
+                self.has_clients = true;
+                notifications.push(true);
+            }
+            // The guarantee is temporary.
+            self.guarentee_client = false;
+        }
+
+        // Notify as soon as possible to prevent further thrashing.
+        if has_kernel_reported_clients && !self.has_clients {
+            log::info!("Notifying {} that it has a client", name);
+            self.has_clients = true;
+            notifications.push(true);
+        }
+
+        // But limit the rate of shutting down the service.
+        if is_called_on_interval && !has_kernel_reported_clients && self.has_clients {
+            log::info!("Notifying {} that it has no client", name);
+            self.has_clients = false;
+            notifications.push(false);
+        }
+
+        notifications
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_update() {
+        let mut tracker = ClientTracker::default();
+        assert_eq!(tracker.update("foo", false, true), Vec::<bool>::new());
+
+        // A client is notified immediately, but no client only on the interval.
+        assert_eq!(tracker.update("foo", true, false), vec![true]);
+        assert_eq!(tracker.update("foo", true, true), Vec::<bool>::new());
+        assert_eq!(tracker.update("foo", false, false), Vec::<bool>::new());
+        assert!(tracker.has_clients);
+        assert_eq!(tracker.update("foo", false, true), vec![false]);
+
+        // A guaranteed client keeps the service until the next interval.
+        tracker.guarentee_client = true;
+        assert_eq!(tracker.update("foo", false, true), vec![true, false]);
+        assert!(!tracker.guarentee_client);
+
+        tracker.guarentee_client = true;
+        assert_eq!(tracker.update("foo", false, false), vec![true]);
+        assert_eq!(tracker.update("foo", false, true), vec![false]);
+    }
+}
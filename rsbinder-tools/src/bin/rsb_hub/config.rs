@@ -0,0 +1,88 @@
+// Copyright 2022 Jeff Kim <hiking90@gmail.com>
+// SPDX-License-Identifier: Apache-2.0
+
+//! The line-based format shared by the configuration files of rsb_hub.
+//!
+//! Each line is split into the tokens separated by whitespace. `#` starts a comment
+//! to the end of the line, and the lines without tokens are skipped.
+
+use std::fmt::Display;
+use std::path::Path;
+
+/// A configuration file parsed from its text.
+pub trait ConfigFile: Sized {
+    fn parse(text: &str) -> Result<Self, String>;
+
+    fn load(path: &Path) -> Result<Self, String> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+        Self::parse(&text).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+}
+
+/// The tokens of a line.
+#[derive(Debug)]
+pub struct Line<'a> {
+    pub number: usize,
+    pub tokens: Vec<&'a str>,
+}
+
+impl Line<'_> {
+    /// An error message with the line number.
+    pub fn error(&self, msg: impl Display) -> String {
+        format!("line {}: {}", self.number, msg)
+    }
+}
+
+/// The lines of `text` which have any token.
+pub fn lines(text: &str) -> impl Iterator<Item = Line<'_>> {
+    text.lines().enumerate().filter_map(|(index, line)| {
+        let tokens: Vec<&str> = line.split('#').next().unwrap_or_default()
+            .split_whitespace()
+            .collect();
+        (!tokens.is_empty()).then_some(Line { number: index + 1, tokens })
+    })
+}
+
+/// A uid by the number or the name of a user.
+pub fn parse_user(value: &str) -> Result<u32, String> {
+    if let Ok(uid) = value.parse() {
+        return Ok(uid);
+    }
+    match nix::unistd::User::from_name(value) {
+        Ok(Some(user)) => Ok(user.uid.as_raw()),
+        _ => Err(format!("unknown user {}", value)),
+    }
+}
+
+/// A gid by the number or the name of a group.
+pub fn parse_group(value: &str) -> Result<u32, String> {
+    if let Ok(gid) = value.parse() {
+        return Ok(gid);
+    }
+    match nix::unistd::Group::from_name(value) {
+        Ok(Some(group)) => Ok(group.gid.as_raw()),
+        _ => Err(format!("unknown group {}", value)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lines() {
+        let lines: Vec<Line> = lines("# A comment\n\n  first  line # trailing\n#\nsecond\n").collect();
+
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0].number, 3);
+        assert_eq!(lines[0].tokens, vec!["first", "line"]);
+        assert_eq!(lines[1].number, 5);
+        assert_eq!(lines[1].tokens, vec!["second"]);
+        assert_eq!(lines[1].error("bad"), "line 5: bad");
+
+        assert_eq!(parse_user("1000"), Ok(1000));
+        assert_eq!(parse_group("0"), Ok(0));
+        assert!(parse_user("no-such-user").is_err());
+    }
+}
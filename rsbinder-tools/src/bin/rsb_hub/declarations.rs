@@ -0,0 +1,118 @@
+// Copyright 2022 Jeff Kim <hiking90@gmail.com>
+// SPDX-License-Identifier: Apache-2.0
+
+//! The declaration manifest which lists the service instances expected on the system,
+//! like the VINTF manifest of Android.
+//!
+//! Each line declares an instance as `<package>.<interface>/<instance>` followed by
+//! its metadata as pairs of a key and a value.
+//!
+//! ```text
+//! # A comment
+//! android.hardware.usb.IUsb/default
+//! my.pkg.IFoo/default updatable-via-apex com.my.foo
+//! ```
+
+use std::collections::BTreeMap;
+
+use crate::config::{self, ConfigFile};
+
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Declaration {
+    /// The name of the APEX which can update the service.
+    pub updatable_via_apex: Option<String>,
+}
+
+#[derive(Debug, Default)]
+pub struct Declarations {
+    // The declarations by the full instance name. It is sorted to list the instances in order.
+    instances: BTreeMap<String, Declaration>,
+}
+
+impl ConfigFile for Declarations {
+    fn parse(text: &str) -> Result<Self, String> {
+        let mut declarations = Declarations::default();
+
+        for line in config::lines(text) {
+            let Some((name, mut metadata)) = line.tokens.split_first() else {
+                continue;
+            };
+
+            if split_instance(name).is_none() {
+                return Err(line.error(format!("{} is not in the form of <interface>/<instance>", name)));
+            }
+
+            let mut declaration = Declaration::default();
+            while let [key, rest @ ..] = metadata {
+                let [value, rest @ ..] = rest else {
+                    return Err(line.error(format!("{} requires a value", key)));
+                };
+                match *key {
+                    "updatable-via-apex" => declaration.updatable_via_apex = Some(value.to_string()),
+                    _ => return Err(line.error(format!("unknown metadata {}", key))),
+                }
+                metadata = rest;
+            }
+
+            if declarations.instances.insert(name.to_string(), declaration).is_some() {
+                return Err(line.error(format!("{} is declared twice", name)));
+            }
+        }
+
+        Ok(declarations)
+    }
+}
+
+impl Declarations {
+    pub fn is_declared(&self, name: &str) -> bool {
+        self.instances.contains_key(name)
+    }
+
+    /// The instances declared for an interface.
+    pub fn instances(&self, interface: &str) -> Vec<String> {
+        self.instances.keys()
+            .filter_map(|name| split_instance(name))
+            .filter(|(iface, _)| *iface == interface)
+            .map(|(_, instance)| instance.to_owned())
+            .collect()
+    }
+
+    pub fn updatable_via_apex(&self, name: &str) -> Option<String> {
+        self.instances.get(name).and_then(|declaration| declaration.updatable_via_apex.clone())
+    }
+}
+
+// Split a full instance name into the interface and the instance.
+fn split_instance(name: &str) -> Option<(&str, &str)> {
+    match name.split_once('/') {
+        Some((interface, instance)) if !interface.is_empty() && !instance.is_empty() => Some((interface, instance)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let declarations = Declarations::parse(r#"
+            # Declared HALs.
+            android.hardware.usb.IUsb/default
+            my.pkg.IFoo/default updatable-via-apex com.my.foo
+            my.pkg.IFoo/secondary  # trailing comment
+        "#).unwrap();
+
+        assert!(declarations.is_declared("android.hardware.usb.IUsb/default"));
+        assert!(!declarations.is_declared("android.hardware.usb.IUsb/other"));
+        assert_eq!(declarations.instances("my.pkg.IFoo"), vec!["default".to_owned(), "secondary".to_owned()]);
+        assert!(declarations.instances("my.pkg.IBar").is_empty());
+        assert_eq!(declarations.updatable_via_apex("my.pkg.IFoo/default"), Some("com.my.foo".to_owned()));
+        assert_eq!(declarations.updatable_via_apex("my.pkg.IFoo/secondary"), None);
+
+        assert!(Declarations::parse("my.pkg.IFoo").is_err());
+        assert!(Declarations::parse("my.pkg.IFoo/default updatable-via-apex").is_err());
+        assert!(Declarations::parse("my.pkg.IFoo/default unknown value").is_err());
+        assert!(Declarations::parse("my.pkg.IFoo/default\nmy.pkg.IFoo/default").is_err());
+    }
+}
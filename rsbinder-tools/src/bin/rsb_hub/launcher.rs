@@ -0,0 +1,109 @@
+// Copyright 2022 Jeff Kim <hiking90@gmail.com>
+// SPDX-License-Identifier: Apache-2.0
+
+//! Start the processes of the services declared in the manifest on demand.
+
+use std::collections::HashSet;
+use std::os::unix::process::CommandExt;
+use std::process::{Child, Command};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::manifest::{Manifest, RestartPolicy, ServiceEntry};
+
+// The delay before restarting a service, not to restart a crashing service in a busy loop.
+const RESTART_DELAY: Duration = Duration::from_secs(1);
+
+pub struct Launcher {
+    manifest: Manifest,
+    // The names of the services whose process is running.
+    running: Mutex<HashSet<String>>,
+}
+
+impl Launcher {
+    pub fn new(manifest: Manifest) -> Arc<Self> {
+        Arc::new(Self {
+            manifest,
+            running: Mutex::new(HashSet::new()),
+        })
+    }
+
+    /// Start the process of a service if it is declared and not running.
+    /// Returns false if the service is not declared.
+    pub fn start(self: &Arc<Self>, name: &str) -> std::io::Result<bool> {
+        let Some(entry) = self.manifest.get(name) else {
+            return Ok(false);
+        };
+
+        let mut running = self.running.lock().unwrap();
+        if running.contains(name) {
+            return Ok(true);
+        }
+
+        let child = Self::spawn(entry)?;
+        running.insert(name.to_owned());
+        self.monitor(entry.clone(), child);
+
+        Ok(true)
+    }
+
+    fn spawn(entry: &ServiceEntry) -> std::io::Result<Child> {
+        let mut command = Command::new(&entry.executable);
+        command.args(&entry.args);
+        // The group must be set first, it can't be changed after the user is changed.
+        if let Some(gid) = entry.group {
+            command.gid(gid);
+        }
+        if let Some(uid) = entry.user {
+            command.uid(uid);
+        }
+
+        let child = command.spawn()
+            .map_err(|e| {
+                log::error!("Failed to start {} for {}: {}", entry.executable.display(), entry.name, e);
+                e
+            })?;
+        log::info!("Started {} for {} (pid {})", entry.executable.display(), entry.name, child.id());
+        Ok(child)
+    }
+
+    // Wait for the process to exit and restart it by the restart policy.
+    fn monitor(self: &Arc<Self>, entry: ServiceEntry, mut child: Child) {
+        let launcher = self.clone();
+        let name = entry.name.clone();
+        let result = std::thread::Builder::new()
+            .name(format!("launcher:{}", child.id()))
+            .spawn(move || loop {
+                let success = match child.wait() {
+                    Ok(status) => {
+                        log::info!("{} for {} exited: {}", entry.executable.display(), entry.name, status);
+                        status.success()
+                    }
+                    Err(err) => {
+                        log::error!("Failed to wait for {}: {}", entry.name, err);
+                        false
+                    }
+                };
+
+                let restart = match entry.restart {
+                    RestartPolicy::Never => false,
+                    RestartPolicy::OnFailure => !success,
+                    RestartPolicy::Always => true,
+                };
+                if restart {
+                    std::thread::sleep(RESTART_DELAY);
+                    if let Ok(restarted) = Self::spawn(&entry) {
+                        child = restarted;
+                        continue;
+                    }
+                }
+
+                launcher.running.lock().unwrap().remove(&entry.name);
+                break;
+            });
+
+        if let Err(err) = result {
+            log::error!("Failed to monitor {}: {}", name, err);
+        }
+    }
+}
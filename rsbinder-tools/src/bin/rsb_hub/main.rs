@@ -0,0 +1,508 @@
+// Copyright 2022 Jeff Kim <hiking90@gmail.com>
+// SPDX-License-Identifier: Apache-2.0
+#![allow(non_snake_case)]
+
+mod config;
+mod manifest;
+mod launcher;
+mod declarations;
+mod access;
+mod clients;
+
+use std::{collections::HashMap, sync::{RwLock, Arc}, time::Duration};
+use hub::{IServiceManager, BnServiceManager, IClientCallback, ServiceDebugInfo, DUMP_FLAG_PRIORITY_DEFAULT};
+use env_logger::Env;
+use rsbinder::*;
+use nix::sys::signal::{SigSet, Signal};
+use launcher::Launcher;
+use manifest::Manifest;
+use declarations::Declarations;
+use access::{AccessControl, Operation};
+use clients::ClientTracker;
+use config::ConfigFile;
+
+struct Service {
+    binder: SIBinder,
+    _allow_isolated: bool,
+    dump_priority: i32,
+    clients: ClientTracker,
+    // The pid of the process which registered the service.
+    debug_pid: i32,
+}
+
+// The interval to check whether the services with client callbacks still have clients.
+const CLIENT_CALLBACK_INTERVAL: Duration = Duration::from_secs(5);
+
+impl Service {
+    fn get_node_strong_ref_count(&self) -> rsbinder::Result<usize> {
+        let info = ProcessState::as_self().node_info_for_ref(&self.binder)?;
+        Ok(info.strong_count as _)
+    }
+}
+
+struct ServiceManagerInner {
+    name_to_service: RwLock<HashMap<String, Service>>,
+    name_to_registration_callbacks: RwLock<HashMap<String, Vec<rsbinder::Strong<dyn hub::android::os::IServiceCallback::IServiceCallback>>>>,
+    name_to_client_callbacks: RwLock<HashMap<String, Vec<rsbinder::Strong<dyn IClientCallback>>>>,
+    launcher: Option<Arc<Launcher>>,
+    declarations: Declarations,
+}
+
+impl ServiceManagerInner {
+    // A service started on demand is not returned until it registers. The clients wait for it
+    // by registerForNotifications(), so no thread of the service manager is blocked.
+    fn try_get_service(&self, name: &str, start_if_not_found: bool) -> rsbinder::status::Result<Option<SIBinder>> {
+        if let Some(binder) = self.find_service(name) {
+            return Ok(Some(binder));
+        }
+
+        if let (true, Some(launcher)) = (start_if_not_found, &self.launcher) {
+            if let Err(err) = launcher.start(name) {
+                log::error!("Failed to start service {}: {}", name, err);
+            }
+        }
+
+        Ok(None)
+    }
+
+    fn find_service(&self, name: &str) -> Option<SIBinder> {
+        self.name_to_service.write().unwrap().get_mut(name).map(|service| {
+            service.clients.guarentee_client = true;
+            service.binder.clone()
+        })
+    }
+
+    fn add_service(&self, name: &str, service: Service) -> rsbinder::status::Result<()> {
+        self.name_to_service.write().unwrap().insert(name.to_owned(), service);
+        Ok(())
+    }
+
+    fn on_registration(&self, name: &str) -> rsbinder::status::Result<()> {
+        if let Some(service) = self.name_to_service.read().unwrap().get(name) {
+            let callbacks = self.name_to_registration_callbacks.read().unwrap().get(name).cloned();
+            if let Some(callbacks) = callbacks {
+                for callback in callbacks {
+                    callback.onRegistration(name, &service.binder)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn list_services(&self, dump_priority: i32) -> rsbinder::status::Result<Vec<String>> {
+        let mut services = Vec::new();
+
+        for (name, service) in self.name_to_service.read().unwrap().iter() {
+            if (service.dump_priority & dump_priority) != 0 {
+                services.push(name.clone());
+            }
+        }
+
+        Ok(services)
+    }
+
+    fn register_for_notifications(&self, name: &str, callback: &rsbinder::Strong<dyn hub::android::os::IServiceCallback::IServiceCallback>) -> rsbinder::status::Result<()> {
+        let mut callbacks = self.name_to_registration_callbacks.write().unwrap();
+        let callbacks = callbacks.entry(name.to_owned()).or_default();
+        callbacks.push(callback.clone());
+
+        if let Some(service) = self.name_to_service.read().unwrap().get(name) {
+            callback.onRegistration(name, &service.binder)?;
+        }
+
+        Ok(())
+    }
+
+    fn unregister_for_notifications(&self, name: &str, callback: &rsbinder::Strong<dyn hub::android::os::IServiceCallback::IServiceCallback>) -> rsbinder::status::Result<()> {
+        let mut callbacks = self.name_to_registration_callbacks.write().unwrap();
+        if let Some(callbacks) = callbacks.get_mut(name) {
+            callbacks.retain(|c| c.as_binder() != callback.as_binder());
+            Ok(())
+        } else {
+            log::error!("Trying to unregister callback, but none exists {}", name);
+            Err(ExceptionCode::IllegalState.into())
+        }
+    }
+
+    fn register_client_callback(&self, name: &str, service: &SIBinder, callback: &rsbinder::Strong<dyn IClientCallback>) -> rsbinder::status::Result<()> {
+        match self.name_to_service.read().unwrap().get(name) {
+            Some(registered) if registered.debug_pid != rsbinder::calling_context().pid => {
+                log::warn!("Only the server of {} can register a client callback", name);
+                return Err((ExceptionCode::Security, "Not the server").into());
+            }
+            Some(registered) if registered.binder == *service => {}
+            Some(_) => {
+                log::warn!("Tried to register client callback for {} but a different service is registered", name);
+                return Err((ExceptionCode::IllegalArgument, "Service mismatch").into());
+            }
+            None => {
+                log::warn!("Could not add callback for nonexistent service: {}", name);
+                return Err((ExceptionCode::IllegalArgument, "Service not found").into());
+            }
+        }
+
+        self.name_to_client_callbacks.write().unwrap()
+            .entry(name.to_owned()).or_default()
+            .push(callback.clone());
+
+        // Notify the current state. The service manager and the transaction hold the service.
+        self.handle_service_client_callback(2, name, false);
+
+        Ok(())
+    }
+
+    fn try_unregister_service(&self, name: &str, binder: &SIBinder) -> rsbinder::status::Result<()> {
+        let caller_pid = rsbinder::calling_context().pid;
+        Self::check_unregister(self.name_to_service.read().unwrap().get(name), name, binder, caller_pid)?;
+
+        // The service manager and the transaction hold the service.
+        const KNOWN_CLIENTS: usize = 2;
+        if self.handle_service_client_callback(KNOWN_CLIENTS, name, false).is_some_and(|count| count > KNOWN_CLIENTS) {
+            log::info!("Tried to unregister {}, but there are clients", name);
+            // Keep the service for a while to reduce the thrashing of the service.
+            if let Some(service) = self.name_to_service.write().unwrap().get_mut(name) {
+                service.clients.guarentee_client = true;
+            }
+            return Err((ExceptionCode::IllegalState, "Service has clients").into());
+        }
+
+        // The service may be replaced or get a client while the lock was released,
+        // so it is checked again under the lock which removes it.
+        let mut services = self.name_to_service.write().unwrap();
+        Self::check_unregister(services.get(name), name, binder, caller_pid)?;
+        services.remove(name);
+        Ok(())
+    }
+
+    fn check_unregister(service: Option<&Service>, name: &str, binder: &SIBinder, caller_pid: i32) -> rsbinder::status::Result<()> {
+        match service {
+            None => {
+                log::warn!("Tried to unregister {}, but that service wasn't registered to begin with", name);
+                Err((ExceptionCode::IllegalState, "Service not found").into())
+            }
+            Some(service) if service.debug_pid != caller_pid => {
+                log::warn!("Only the server can unregister {}", name);
+                Err((ExceptionCode::Security, "Not the server").into())
+            }
+            Some(service) if service.binder != *binder => {
+                log::warn!("Tried to unregister {}, but a different service is registered under this name", name);
+                Err((ExceptionCode::IllegalState, "Service mismatch").into())
+            }
+            Some(service) if service.clients.guarentee_client => {
+                log::info!("Tried to unregister {}, but there is about to be a client", name);
+                Err((ExceptionCode::IllegalState, "Pending client").into())
+            }
+            Some(_) => Ok(()),
+        }
+    }
+
+    fn service_debug_info(&self) -> Vec<ServiceDebugInfo> {
+        let mut infos: Vec<ServiceDebugInfo> = self.name_to_service.read().unwrap().iter()
+            .map(|(name, service)| ServiceDebugInfo {
+                name: name.clone(),
+                debugPid: service.debug_pid,
+            })
+            .collect();
+        infos.sort_by(|a, b| a.name.cmp(&b.name));
+        infos
+    }
+
+    // Check the clients of all services with client callbacks.
+    fn handle_client_callbacks(&self) {
+        let names: Vec<String> = self.name_to_client_callbacks.read().unwrap().keys().cloned().collect();
+        for name in names {
+            self.handle_service_client_callback(1, &name, true);
+        }
+    }
+
+    // Send onClients() to the client callbacks of a service if its clients are changed.
+    // `known_clients` is the number of strong references which are not held by clients.
+    // Returns the number of strong references of the service node.
+    fn handle_service_client_callback(&self, known_clients: usize, name: &str, is_called_on_interval: bool) -> Option<usize> {
+        let (binder, count, notifications) = {
+            let mut services = self.name_to_service.write().unwrap();
+            let service = services.get_mut(name)?;
+            if !self.name_to_client_callbacks.read().unwrap().contains_key(name) {
+                return None;
+            }
+
+            let count = match service.get_node_strong_ref_count() {
+                Ok(count) => count,
+                Err(err) => {
+                    log::warn!("Failed to get the strong reference count of {}: {}", name, err);
+                    return None;
+                }
+            };
+            let has_kernel_reported_clients = count > known_clients;
+
+            let notifications = service.clients.update(name, has_kernel_reported_clients, is_called_on_interval);
+            (service.binder.clone(), count, notifications)
+        };
+
+        if !notifications.is_empty() {
+            let callbacks = self.name_to_client_callbacks.read().unwrap().get(name).cloned().unwrap_or_default();
+            for has_clients in notifications {
+                for callback in &callbacks {
+                    if let Err(err) = callback.onClients(&binder, has_clients) {
+                        log::warn!("Failed to send onClients({}) to the client callback of {}: {}", has_clients, name, err);
+                    }
+                }
+            }
+        }
+
+        Some(count)
+    }
+}
+
+impl Default for ServiceManagerInner {
+    fn default() -> Self {
+        Self {
+            name_to_service: RwLock::new(HashMap::new()),
+            name_to_registration_callbacks: RwLock::new(HashMap::new()),
+            name_to_client_callbacks: RwLock::new(HashMap::new()),
+            launcher: None,
+            declarations: Declarations::default(),
+        }
+    }
+}
+
+impl rsbinder::DeathRecipient for ServiceManagerInner {
+    fn binder_died(&self, who: &rsbinder::WIBinder) {
+        let mut removed = Vec::new();
+        self.name_to_service.write().unwrap().retain(|name, service| {
+            let alive = SIBinder::downgrade(&service.binder) != *who;
+            if !alive {
+                removed.push(name.clone());
+            }
+            alive
+        });
+
+        self.name_to_client_callbacks.write().unwrap().retain(|name, callbacks| {
+            callbacks.retain(|callback| {
+                SIBinder::downgrade(&callback.as_binder()) != *who
+            });
+            !callbacks.is_empty() && !removed.contains(name)
+        });
+
+        self.name_to_registration_callbacks.write().unwrap().retain(|_, callbacks| {
+            callbacks.retain(|callback| {
+                SIBinder::downgrade(&callback.as_binder()) != *who
+            });
+            !callbacks.is_empty()
+        });
+    }
+}
+
+struct ServiceManager {
+    inner: Arc<ServiceManagerInner>,
+    access: Arc<AccessControl>,
+}
+
+impl ServiceManager {
+    fn is_valid_service_name(name: &str) -> bool {
+        if name.is_empty() || name.len() > 127 {
+            return false;
+        }
+        for c in name.chars() {
+            if c == '_' || c == '-' || c == '.' || c == '/' {
+                continue;
+            }
+            if c.is_ascii_lowercase() {
+                continue;
+            }
+            if c.is_ascii_uppercase() {
+                continue;
+            }
+            if c.is_ascii_digit() {
+                continue;
+            }
+            return false;
+        }
+
+        true
+    }
+}
+
+impl ServiceManager {
+    fn new(launcher: Option<Arc<Launcher>>, declarations: Declarations, access: Arc<AccessControl>) -> Self {
+        Self {
+            inner: Arc::new(ServiceManagerInner {
+                launcher,
+                declarations,
+                ..Default::default()
+            }),
+            access,
+        }
+    }
+}
+
+impl Interface for ServiceManager {}
+
+impl IServiceManager for ServiceManager {
+    fn getService(&self,_arg_name: &str) -> rsbinder::status::Result<Option<rsbinder::SIBinder>> {
+        self.access.check(Operation::Find, _arg_name)?;
+        self.inner.try_get_service(_arg_name, true)
+    }
+
+    fn addService(&self, name: &str, service: &SIBinder, allowIsolated: bool, dumpPriority: i32) -> rsbinder::status::Result<()> {
+        if !Self::is_valid_service_name(name) {
+            return Err(ExceptionCode::IllegalArgument.into());
+        }
+        self.access.check(Operation::Add, name)?;
+
+        if service.as_proxy().is_some() {
+            service.link_to_death(self.inner.clone())?;
+        }
+        self.inner.add_service(name, Service {
+            binder: service.clone(),
+            _allow_isolated: allowIsolated,
+            dump_priority: dumpPriority,
+            clients: ClientTracker::default(),
+            debug_pid: rsbinder::calling_context().pid,
+        })?;
+
+        self.inner.on_registration(name)?;
+
+        Ok(())
+    }
+
+    fn checkService(&self, name: &str) -> rsbinder::status::Result<Option<SIBinder>> {
+        self.access.check(Operation::Find, name)?;
+        self.inner.try_get_service(name, false)
+    }
+
+    fn listServices(&self, dumpPriority: i32) -> rsbinder::status::Result<Vec<String>> {
+        Ok(self.access.filter(Operation::List, self.inner.list_services(dumpPriority)?, String::as_str))
+    }
+
+    fn registerForNotifications(&self, _arg_name: &str, _arg_callback: &rsbinder::Strong<dyn hub::android::os::IServiceCallback::IServiceCallback>) -> rsbinder::status::Result<()> {
+        if !Self::is_valid_service_name(_arg_name) {
+            return Err(ExceptionCode::IllegalArgument.into());
+        }
+        self.access.check(Operation::Find, _arg_name)?;
+
+        _arg_callback.as_binder().link_to_death(self.inner.clone())?;
+
+        self.inner.register_for_notifications(_arg_name, _arg_callback)
+    }
+
+    fn unregisterForNotifications(&self, _arg_name: &str, _arg_callback: &rsbinder::Strong<dyn hub::android::os::IServiceCallback::IServiceCallback>) -> rsbinder::status::Result<()> {
+        self.access.check(Operation::Find, _arg_name)?;
+        self.inner.unregister_for_notifications(_arg_name, _arg_callback)
+    }
+
+    fn isDeclared(&self, name: &str) -> rsbinder::status::Result<bool> {
+        self.access.check(Operation::Find, name)?;
+        Ok(self.inner.declarations.is_declared(name))
+    }
+
+    fn getDeclaredInstances(&self, iface: &str) -> rsbinder::status::Result<Vec<String>> {
+        let names = self.inner.declarations.instances(iface).into_iter()
+            .map(|instance| format!("{}/{}", iface, instance))
+            .collect();
+        let prefix = format!("{}/", iface);
+        Ok(self.access.filter(Operation::Find, names, String::as_str).into_iter()
+            .filter_map(|name| name.strip_prefix(&prefix).map(str::to_owned))
+            .collect())
+    }
+
+    fn updatableViaApex(&self, name: &str) -> rsbinder::status::Result<Option<String>> {
+        self.access.check(Operation::Find, name)?;
+        Ok(self.inner.declarations.updatable_via_apex(name))
+    }
+
+    fn getConnectionInfo(&self,_arg_name: &str) -> rsbinder::status::Result<Option<hub::android::os::ConnectionInfo::ConnectionInfo>> {
+        self.access.check(Operation::Find, _arg_name)?;
+        println!("getConnectionInfo");
+        Ok(None)
+    }
+
+    fn registerClientCallback(&self, name: &str, service: &rsbinder::SIBinder, callback: &rsbinder::Strong<dyn IClientCallback>) -> rsbinder::status::Result<()> {
+        self.access.check(Operation::Add, name)?;
+        callback.as_binder().link_to_death(self.inner.clone())?;
+
+        self.inner.register_client_callback(name, service, callback)
+    }
+
+    fn tryUnregisterService(&self, name: &str, service: &rsbinder::SIBinder) -> rsbinder::status::Result<()> {
+        self.access.check(Operation::Add, name)?;
+        self.inner.try_unregister_service(name, service)
+    }
+
+    fn getServiceDebugInfo(&self) -> rsbinder::status::Result<Vec<ServiceDebugInfo>> {
+        Ok(self.access.filter(Operation::List, self.inner.service_debug_info(), |info| info.name.as_str()))
+    }
+}
+
+fn main() -> std::result::Result<(), Box<dyn std::error::Error>> {
+    let mut manifest = None;
+    let mut declarations = None;
+    let mut policy = None;
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-m" | "--manifest" => manifest = Some(args.next().ok_or("--manifest requires a path")?),
+            "-d" | "--declarations" => declarations = Some(args.next().ok_or("--declarations requires a path")?),
+            "-p" | "--policy" => policy = Some(args.next().ok_or("--policy requires a path")?),
+            _ => {
+                println!("rsb_hub - The service manager of rsbinder");
+                println!("Usage: rsb_hub [--manifest <service manifest>] [--declarations <declaration manifest>]");
+                println!("               [--policy <access control policy>]");
+                return Ok(())
+            }
+        }
+    }
+
+    env_logger::Builder::from_env(Env::default().default_filter_or("warn")).init();
+
+    let launcher = match manifest {
+        Some(path) => Some(Launcher::new(Manifest::load(path.as_ref())?)),
+        None => None,
+    };
+    let declarations = match declarations {
+        Some(path) => Declarations::load(path.as_ref())?,
+        None => Declarations::default(),
+    };
+    let access = Arc::new(AccessControl::new(policy.map(Into::into))?);
+
+    // Reload the policy on SIGHUP. The signal is blocked before any thread is spawned,
+    // so it is delivered only to the thread waiting for it.
+    let mut signals = SigSet::empty();
+    signals.add(Signal::SIGHUP);
+    signals.thread_block()?;
+    let reloader = access.clone();
+    std::thread::Builder::new()
+        .name("policy_reloader".to_owned())
+        .spawn(move || loop {
+            match signals.wait() {
+                Ok(_) => reloader.reload(),
+                Err(err) => {
+                    log::error!("Failed to wait for SIGHUP: {}", err);
+                    break;
+                }
+            }
+        })?;
+
+    ProcessState::builder().max_threads(1).init()?;
+
+    let manager = ServiceManager::new(launcher, declarations, access);
+
+    // Notify the services without clients periodically.
+    let inner = manager.inner.clone();
+    std::thread::Builder::new()
+        .name("client_callbacks".to_owned())
+        .spawn(move || loop {
+            std::thread::sleep(CLIENT_CALLBACK_INTERVAL);
+            inner.handle_client_callbacks();
+        })?;
+
+    // Create a binder service.
+    let service = BnServiceManager::new_binder(manager);
+    // Receive the security contexts of the clients.
+    native::Binder::<BnServiceManager>::try_from(service.as_binder())?.set_requesting_sid(true);
+    service.addService("manager", &service.as_binder(), false, DUMP_FLAG_PRIORITY_DEFAULT)?;
+
+    ProcessState::as_self().become_context_manager(service.as_binder())?;
+
+    Ok(ProcessState::join_thread_pool()?)
+}
@@ -0,0 +1,153 @@
+// Copyright 2022 Jeff Kim <hiking90@gmail.com>
+// SPDX-License-Identifier: Apache-2.0
+
+//! The service manifest which declares the services started on demand.
+//!
+//! The format is similar to the service section of Android's init.rc.
+//! A `service` line declares a service name, the executable and its arguments,
+//! and the indented lines below it set the options of the service.
+//!
+//! ```text
+//! # A comment
+//! service my.hello /usr/bin/hello_service --verbose
+//!     user 1000
+//!     group 1000
+//!     restart on-failure
+//! ```
+
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+use crate::config::{self, ConfigFile, parse_group, parse_user};
+
+/// What to do when the process of a service exits.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RestartPolicy {
+    /// Start it again only when a client asks for the service.
+    #[default]
+    Never,
+    /// Restart it if it exits with a failure.
+    OnFailure,
+    /// Restart it whenever it exits.
+    Always,
+}
+
+/// A service declared in the manifest.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ServiceEntry {
+    pub name: String,
+    pub executable: PathBuf,
+    pub args: Vec<String>,
+    pub user: Option<u32>,
+    pub group: Option<u32>,
+    pub restart: RestartPolicy,
+}
+
+#[derive(Debug, Default)]
+pub struct Manifest {
+    services: HashMap<String, ServiceEntry>,
+}
+
+impl ConfigFile for Manifest {
+    fn parse(text: &str) -> Result<Self, String> {
+        let mut manifest = Manifest::default();
+        let mut current: Option<ServiceEntry> = None;
+
+        for line in config::lines(text) {
+            let Some((keyword, args)) = line.tokens.split_first() else {
+                continue;
+            };
+
+            if *keyword == "service" {
+                if let Some(entry) = current.take() {
+                    manifest.insert(entry).map_err(|e| line.error(e))?;
+                }
+                let [name, executable, args @ ..] = args else {
+                    return Err(line.error("service requires a name and an executable"));
+                };
+                current = Some(ServiceEntry {
+                    name: name.to_string(),
+                    executable: PathBuf::from(executable),
+                    args: args.iter().map(|arg| arg.to_string()).collect(),
+                    user: None,
+                    group: None,
+                    restart: RestartPolicy::default(),
+                });
+                continue;
+            }
+
+            let Some(entry) = current.as_mut() else {
+                return Err(line.error(format!("option {} is out of a service", keyword)));
+            };
+            let [value] = args else {
+                return Err(line.error(format!("option {} requires a value", keyword)));
+            };
+
+            match *keyword {
+                "user" => entry.user = Some(parse_user(value).map_err(|e| line.error(e))?),
+                "group" => entry.group = Some(parse_group(value).map_err(|e| line.error(e))?),
+                "restart" => entry.restart = match *value {
+                    "never" => RestartPolicy::Never,
+                    "on-failure" => RestartPolicy::OnFailure,
+                    "always" => RestartPolicy::Always,
+                    _ => return Err(line.error(format!("unknown restart policy {}", value))),
+                },
+                _ => return Err(line.error(format!("unknown option {}", keyword))),
+            }
+        }
+
+        if let Some(entry) = current.take() {
+            manifest.insert(entry)?;
+        }
+
+        Ok(manifest)
+    }
+}
+
+impl Manifest {
+    fn insert(&mut self, entry: ServiceEntry) -> Result<(), String> {
+        if self.services.contains_key(&entry.name) {
+            return Err(format!("service {} is declared twice", entry.name));
+        }
+        self.services.insert(entry.name.clone(), entry);
+        Ok(())
+    }
+
+    pub fn get(&self, name: &str) -> Option<&ServiceEntry> {
+        self.services.get(name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let manifest = Manifest::parse(r#"
+            # Services started on demand.
+            service my.hello /usr/bin/hello_service --verbose  # trailing comment
+                user 1000
+                restart on-failure
+
+            service my.echo /usr/bin/echo_service
+        "#).unwrap();
+
+        let hello = manifest.get("my.hello").unwrap();
+        assert_eq!(hello.executable, PathBuf::from("/usr/bin/hello_service"));
+        assert_eq!(hello.args, vec!["--verbose".to_owned()]);
+        assert_eq!(hello.user, Some(1000));
+        assert_eq!(hello.group, None);
+        assert_eq!(hello.restart, RestartPolicy::OnFailure);
+
+        let echo = manifest.get("my.echo").unwrap();
+        assert!(echo.args.is_empty());
+        assert_eq!(echo.restart, RestartPolicy::Never);
+        assert!(manifest.get("my.unknown").is_none());
+
+        assert!(Manifest::parse("user 1000").is_err());
+        assert!(Manifest::parse("service my.hello").is_err());
+        assert!(Manifest::parse("service my.hello /bin/true\n restart sometimes").is_err());
+        assert!(Manifest::parse("service my.hello /bin/true\nservice my.hello /bin/true").is_err());
+    }
+}
@@ -0,0 +1,100 @@
+// Copyright 2022 Jeff Kim <hiking90@gmail.com>
+// SPDX-License-Identifier: Apache-2.0
+
+use std::fs::{File, OpenOptions};
+use std::io::BufReader;
+
+use rsbinder::*;
+use anstyle::*;
+
+fn print_usage() {
+    println!("rsb_replay - Record and replay the transactions of a binder service");
+    println!("Usage: rsb_replay start <service_name> <recording_file>");
+    println!("       rsb_replay stop <service_name>");
+    println!("       rsb_replay replay <service_name> <recording_file>");
+}
+
+fn log_ok(msg: &str) {
+    let style = Style::new().fg_color(Some(AnsiColor::Green.into())).bold();
+    println!("[{}OK{}] {}", style.render(), style.render_reset(), msg);
+}
+
+fn log_fail(msg: &str) {
+    let style = Style::new().fg_color(Some(AnsiColor::Red.into())).bold();
+    println!("[{}FAIL{}] {}", style.render(), style.render_reset(), msg);
+}
+
+fn get_proxy(name: &str) -> std::result::Result<SIBinder, Box<dyn std::error::Error>> {
+    let binder = hub::get_service(name)
+        .ok_or_else(|| format!("Service {} is not found", name))?;
+    if binder.as_proxy().is_none() {
+        return Err(format!("Service {} is not a remote binder", name).into());
+    }
+    Ok(binder)
+}
+
+fn replay(proxy: &ProxyHandle, recording: File) -> std::result::Result<(), Box<dyn std::error::Error>> {
+    let mut reader = BufReader::new(recording);
+    let mut count = 0;
+    let mut failed = 0;
+
+    while let Some(transaction) = RecordedTransaction::read_from(&mut reader)? {
+        count += 1;
+
+        let data = Parcel::from_vec(transaction.data);
+        let (status, reply): (i32, _) = match proxy.submit_transact(transaction.code, &data, transaction.flags) {
+            Ok(reply) => (StatusCode::Ok.into(), reply),
+            Err(err) => (err.into(), None),
+        };
+
+        let msg = format!("#{} {} code {} status {}", count, transaction.interface_name, transaction.code, status);
+        if status != transaction.returned_status {
+            failed += 1;
+            log_fail(&format!("{}, but {} was recorded", msg, transaction.returned_status));
+        } else if (transaction.flags & FLAG_ONEWAY) == 0 &&
+            reply.as_ref().map(|reply| reply.as_slice()).unwrap_or_default() != transaction.reply.as_slice() {
+            failed += 1;
+            log_fail(&format!("{}, but the reply is different from the recorded one", msg));
+        } else {
+            log_ok(&msg);
+        }
+    }
+
+    println!("{} transactions were replayed and {} of them were different.", count, failed);
+    Ok(())
+}
+
+fn main() -> std::result::Result<(), Box<dyn std::error::Error>> {
+    let args: Vec<String> = std::env::args().collect();
+
+    let command = args.get(1).map(|s| s.as_str());
+    let valid = matches!((command, args.len()), (Some("start"), 4) | (Some("stop"), 3) | (Some("replay"), 4));
+    if !valid {
+        print_usage();
+        return Ok(())
+    }
+
+    env_logger::init();
+
+    ProcessState::init(DEFAULT_BINDER_PATH, 0);
+
+    let binder = get_proxy(&args[2])?;
+    let proxy = binder.as_proxy().expect("Service is not a remote binder");
+
+    match command {
+        Some("start") => {
+            let file = OpenOptions::new().write(true).create(true).truncate(true).open(&args[3])?;
+            proxy.start_recording(ParcelFileDescriptor::new(file))?;
+            log_ok(&format!("{} started recording to {}", args[2], args[3]));
+        }
+        Some("stop") => {
+            proxy.stop_recording()?;
+            log_ok(&format!("{} stopped recording", args[2]));
+        }
+        _ => {
+            replay(proxy, File::open(&args[3])?)?;
+        }
+    }
+
+    Ok(())
+}
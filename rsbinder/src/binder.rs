@@ -82,6 +82,10 @@ pub const LIKE_TRANSACTION: u32 = b_pack_chars('_', 'L', 'I', 'K');
 
 pub const INTERFACE_HEADER: u32  = b_pack_chars('S', 'Y', 'S', 'T');
 
+// The interface receiving the result of SHELL_COMMAND_TRANSACTION.
+// It has a single oneway method, send(int resultCode), at FIRST_CALL_TRANSACTION.
+pub const RESULT_RECEIVER_DESCRIPTOR: &str = "com.android.internal.os.IResultReceiver";
+
 /// Super-trait for Binder interfaces.
 ///
 /// This trait allows conversion of a Binder interface trait object into an
@@ -102,6 +106,17 @@ pub trait Interface: Send + Sync {
     fn dump(&self, _writer: &mut dyn std::io::Write, _args: &[String]) -> Result<()> {
         Ok(())
     }
+
+    /// Shell command transaction handler for this Binder object.
+    ///
+    /// This handler is invoked by the shell command transaction like `rsb_cmd <service> args...`.
+    /// The result of the handler is sent back to the result receiver of the caller.
+    /// It rejects the command by default.
+    fn handle_shell_command(&self, _stdin: &mut dyn std::io::Read, _stdout: &mut dyn std::io::Write,
+        stderr: &mut dyn std::io::Write, _args: &[String]) -> Result<()> {
+        let _ = writeln!(stderr, "No shell command implementation.");
+        Err(StatusCode::InvalidOperation)
+    }
 }
 
 /// Trait for converting a generic Binder object into a specific Binder
@@ -172,6 +187,17 @@ pub trait IBinder: Any + Send + Sync {
     fn descriptor(&self) -> &str;
     fn is_remote(&self) -> bool;
 
+    /// Whether transactions to this local binder carry the security context of the caller.
+    fn is_requesting_sid(&self) -> bool {
+        false
+    }
+
+    /// Get the extension binder of this object, if any.
+    /// The extension is set by Binder::set_extension() of the service.
+    fn get_extension(&self) -> Result<Option<SIBinder>> {
+        Ok(None)
+    }
+
     fn inc_strong(&self, strong: &SIBinder) -> Result<()>;
     fn attempt_inc_strong(&self) -> bool;
     fn dec_strong(&self, strong: Option<ManuallyDrop<SIBinder>>) -> Result<()>;
@@ -217,6 +243,14 @@ pub trait Remotable: Send + Sync {
     /// Handle a request to invoke the dump transaction on this
     /// object.
     fn on_dump(&self, writer: &mut dyn std::io::Write, args: &[String]) -> Result<()>;
+
+    /// Handle a request to invoke the shell command transaction on this
+    /// object.
+    fn on_shell_command(&self, _stdin: &mut dyn std::io::Read, _stdout: &mut dyn std::io::Write,
+        stderr: &mut dyn std::io::Write, _args: &[String]) -> Result<()> {
+        let _ = writeln!(stderr, "No shell command implementation.");
+        Err(StatusCode::InvalidOperation)
+    }
 }
 
 /// A transactable object that can be used to process Binder commands.
@@ -341,6 +375,10 @@ impl SIBinder {
         raw
     }
 
+    pub(crate) fn as_raw(&self) -> *const dyn IBinder {
+        Arc::as_ptr(&self.inner)
+    }
+
     pub(crate) fn from_raw(raw: *const dyn IBinder) -> Self {
         let inner = unsafe { Arc::from_raw(raw) };
         Self { inner }
@@ -449,6 +487,10 @@ impl WIBinder {
     pub fn upgrade(&self) -> Result<SIBinder> {
         SIBinder::new_with_inner(Arc::clone(&self.inner))
     }
+
+    pub(crate) fn as_binder(&self) -> &dyn IBinder {
+        self.inner.deref()
+    }
 }
 
 impl Debug for WIBinder {
@@ -17,8 +17,14 @@
  * limitations under the License.
  */
 
+ use std::collections::VecDeque;
  use std::future::Future;
  use std::pin::Pin;
+ use std::sync::{Arc, Condvar, Mutex};
+ use std::sync::atomic::{AtomicUsize, Ordering};
+ use std::task::{Context, Poll};
+
+ use crate::thread_state::{self, CallingContext};
 
  /// A type alias for a pinned, boxed future that lets you write shorter code without littering it
  /// with Pin and Send bounds.
@@ -64,4 +70,190 @@
  pub trait BinderAsyncRuntime {
      /// Block on the provided future, running it to completion and returning its output.
      fn block_on<F: Future>(&self, future: F) -> F::Output;
+
+     /// Run the handler of a oneway transaction without waiting for it to complete.
+     ///
+     /// The binder thread returns to the driver as soon as this returns. The oneway
+     /// transactions of a binder are passed through its [`OnewayQueue`], so they are handled
+     /// in order. The default implementation runs the handler to completion on the calling
+     /// binder thread.
+     fn spawn_oneway(&self, future: BoxFuture<'static, ()>) {
+         self.block_on(future)
+     }
+ }
+
+ /// The default maximum number of pending oneway handlers of async services in the process.
+ pub const DEFAULT_MAX_ONEWAY_TASKS: usize = 64;
+
+ static MAX_ONEWAY_TASKS: AtomicUsize = AtomicUsize::new(DEFAULT_MAX_ONEWAY_TASKS);
+ // The number of oneway handlers which are queued or running.
+ static ONEWAY_TASKS: (Mutex<usize>, Condvar) = (Mutex::new(0), Condvar::new());
+
+ /// Set the maximum number of pending oneway handlers of async services in the process.
+ ///
+ /// When the limit is reached, the binder thread waits for a handler to finish before it
+ /// takes the next command from the driver. It takes effect for the next oneway transaction.
+ pub fn set_max_oneway_tasks(max: usize) {
+     MAX_ONEWAY_TASKS.store(max.max(1), Ordering::Relaxed);
+     ONEWAY_TASKS.1.notify_all();
+ }
+
+ // A slot of the limit of oneway handlers, which is released when the handler is finished.
+ struct OnewayPermit;
+
+ impl OnewayPermit {
+     // It blocks the binder thread, never a task of the runtime.
+     fn acquire() -> Self {
+         let (count, condvar) = &ONEWAY_TASKS;
+         let mut count = count.lock().unwrap();
+         while *count >= MAX_ONEWAY_TASKS.load(Ordering::Relaxed) {
+             count = condvar.wait(count).unwrap();
+         }
+         *count += 1;
+         OnewayPermit
+     }
+ }
+
+ impl Drop for OnewayPermit {
+     fn drop(&mut self) {
+         let (count, condvar) = &ONEWAY_TASKS;
+         *count.lock().unwrap_or_else(|e| e.into_inner()) -= 1;
+         condvar.notify_all();
+     }
+ }
+
+ // The handler of a oneway transaction, which is polled as the caller of the transaction
+ // because the transaction is finished before it runs.
+ struct OnewayHandler {
+     caller: CallingContext,
+     future: BoxFuture<'static, ()>,
+ }
+
+ impl Future for OnewayHandler {
+     type Output = ();
+
+     fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+         let this = &mut *self;
+         thread_state::with_oneway_caller(&this.caller, || this.future.as_mut().poll(cx))
+     }
+ }
+
+ #[derive(Default)]
+ struct OnewayState {
+     pending: VecDeque<BoxFuture<'static, ()>>,
+     running: bool,
+ }
+
+ /// The queue of the oneway handlers of a binder.
+ ///
+ /// The binder driver delivers the oneway transactions of a binder one by one, and the
+ /// handlers are run one by one in that order on the runtime. The handlers of different
+ /// binders run concurrently.
+ ///
+ /// A handler gets the caller of its transaction from [`crate::calling_context()`].
+ /// The other state of the transaction, e.g. the work source, isn't available to it.
+ #[derive(Default)]
+ pub struct OnewayQueue(Arc<Mutex<OnewayState>>);
+
+ impl OnewayQueue {
+     /// Queue the handler of a oneway transaction `name`, and start running the queue on `rt`
+     /// if it is not running. It must be called while the transaction is handled.
+     ///
+     /// The error returned by the handler can't be delivered to the caller, so it is logged.
+     pub fn push<R: BinderAsyncRuntime + ?Sized>(&self, rt: &R, name: &'static str,
+             future: BoxFuture<'static, crate::status::Result<()>>) {
+         let permit = OnewayPermit::acquire();
+         let future = Box::pin(OnewayHandler {
+             caller: thread_state::calling_context(),
+             future: Box::pin(async move {
+                 if let Err(err) = future.await {
+                     log::error!("The oneway transaction {} failed: {}", name, err);
+                 }
+                 drop(permit);
+             }),
+         });
+
+         {
+             let mut state = self.0.lock().unwrap();
+             state.pending.push_back(future);
+             if state.running {
+                 return;
+             }
+             state.running = true;
+         }
+
+         let state = self.0.clone();
+         rt.spawn_oneway(Box::pin(async move {
+             loop {
+                 // The flag is cleared under the same lock which finds the queue empty.
+                 let next = {
+                     let mut state = state.lock().unwrap();
+                     match state.pending.pop_front() {
+                         Some(next) => next,
+                         None => {
+                             state.running = false;
+                             return;
+                         }
+                     }
+                 };
+                 next.await;
+             }
+         }));
+     }
+ }
+
+ #[cfg(test)]
+ mod tests {
+     use super::*;
+     use std::sync::mpsc::{channel, Sender};
+
+     // Run the futures on new threads, like a multi-threaded runtime.
+     struct ThreadRuntime;
+
+     struct ThreadWaker(std::thread::Thread);
+
+     impl std::task::Wake for ThreadWaker {
+         fn wake(self: Arc<Self>) {
+             self.0.unpark();
+         }
+     }
+
+     impl BinderAsyncRuntime for ThreadRuntime {
+         fn block_on<F: Future>(&self, _future: F) -> F::Output {
+             unimplemented!()
+         }
+
+         fn spawn_oneway(&self, mut future: BoxFuture<'static, ()>) {
+             std::thread::spawn(move || {
+                 let waker = std::task::Waker::from(Arc::new(ThreadWaker(std::thread::current())));
+                 while future.as_mut().poll(&mut std::task::Context::from_waker(&waker)).is_pending() {
+                     std::thread::park();
+                 }
+             });
+         }
+     }
+
+     fn handler(sender: &Sender<(usize, CallingContext)>, i: usize) -> BoxFuture<'static, crate::status::Result<()>> {
+         let sender = sender.clone();
+         Box::pin(async move {
+             // The earlier handlers are slower, to be overtaken if they ran concurrently.
+             std::thread::sleep(std::time::Duration::from_millis((10 - i) as u64));
+             sender.send((i, crate::calling_context())).unwrap();
+             Ok(())
+         })
+     }
+
+     #[test]
+     fn test_oneway_queue_order() {
+         let (sender, receiver) = channel();
+         let queue = OnewayQueue::default();
+         let caller = |i: usize| CallingContext { pid: i as _, uid: 1000, sid: None };
+         for i in 0..10 {
+             // The handlers are queued while the transactions from the callers are handled.
+             thread_state::with_oneway_caller(&caller(i), || queue.push(&ThreadRuntime, "handler", handler(&sender, i)));
+         }
+
+         let received: Vec<(usize, CallingContext)> = (0..10).map(|_| receiver.recv().unwrap()).collect();
+         assert_eq!(received, (0..10).map(|i| (i, caller(i))).collect::<Vec<_>>());
+     }
  }
@@ -2,12 +2,14 @@
 // SPDX-License-Identifier: Apache-2.0
 
 use std::mem::ManuallyDrop;
+use std::sync::Arc;
 
 pub(crate) use crate::sys::binder::flat_binder_object;
 use crate::{
     binder::*,
     error::*,
     process_state,
+    thread_state,
     sys::*,
 };
 
@@ -64,7 +66,7 @@ impl flat_binder_object {
         self.cookie = cookie;
     }
 
-    pub(crate) fn acquire(&self) -> Result<()> {
+    pub(crate) fn acquire(&self, process: impl FnOnce() -> Arc<process_state::ProcessState>) -> Result<()> {
         match self.hdr.type_ {
             BINDER_TYPE_BINDER => {
                 if self.pointer() != 0 {
@@ -75,12 +77,16 @@ impl flat_binder_object {
                 Ok(())
             }
             BINDER_TYPE_HANDLE => {
-                process_state::ProcessState::as_self().strong_proxy_for_handle(self.handle())?.increase()
+                process().strong_proxy_for_handle(self.handle())?.increase()
             }
             BINDER_TYPE_FD => {
                 // Notion to do.
                 Ok(())
             }
+            BINDER_TYPE_PTR | BINDER_TYPE_FDA => {
+                // Buffers and fd arrays are owned by the parcel.
+                Ok(())
+            }
             _ => {
                 log::error!("Invalid object type {:08x}", self.hdr.type_);
                 Err(StatusCode::InvalidOperation)
@@ -88,7 +94,7 @@ impl flat_binder_object {
         }
     }
 
-    pub(crate) fn release(&self) -> Result<()> {
+    pub(crate) fn release(&self, process: impl FnOnce() -> Arc<process_state::ProcessState>) -> Result<()> {
         match self.hdr.type_ {
             BINDER_TYPE_BINDER => {
                 if self.pointer() != 0 {
@@ -98,7 +104,7 @@ impl flat_binder_object {
                 Ok(())
             }
             BINDER_TYPE_HANDLE => {
-                process_state::ProcessState::as_self().strong_proxy_for_handle(self.handle())?.decrease()
+                process().strong_proxy_for_handle(self.handle())?.decrease()
             }
             BINDER_TYPE_FD => {
                 if self.cookie != 0 {   // owned
@@ -107,6 +113,9 @@ impl flat_binder_object {
 
                 Ok(())
             }
+            BINDER_TYPE_PTR | BINDER_TYPE_FDA => {
+                Ok(())
+            }
             _ => {
                 log::error!("Invalid object type {:08x}", self.hdr.type_);
                 Err(StatusCode::InvalidOperation)
@@ -136,7 +145,9 @@ fn sched_policy_mask(policy: u32, priority: u32) -> u32 {
 
 impl From<&SIBinder> for flat_binder_object {
     fn from(binder: &SIBinder) -> Self {
-        let sched_bits = if !process_state::ProcessState::as_self().background_scheduling_disabled() {
+        let process = binder.as_proxy().and_then(|proxy| proxy.process().ok())
+            .unwrap_or_else(thread_state::current_process);
+        let sched_bits = if !process.background_scheduling_disabled() {
             sched_policy_mask(SCHED_NORMAL, 19)
         } else {
             0
@@ -154,6 +165,11 @@ impl From<&SIBinder> for flat_binder_object {
                 cookie: 0,
             }
         } else {
+            let sid_bits = if binder.is_requesting_sid() {
+                FLAT_BINDER_FLAG_TXN_SECURITY_CTX
+            } else {
+                0
+            };
             let strong = binder.clone();
             let (binder, cookie) = split_fat_pointer(strong.into_raw());
 
@@ -161,7 +177,7 @@ impl From<&SIBinder> for flat_binder_object {
                 hdr: binder_object_header {
                     type_: BINDER_TYPE_BINDER
                 },
-                flags: FLAT_BINDER_FLAG_ACCEPTS_FDS | sched_bits,
+                flags: FLAT_BINDER_FLAG_ACCEPTS_FDS | sched_bits | sid_bits,
                 __bindgen_anon_1: flat_binder_object__bindgen_ty_1 {
                     binder: binder as _,
                 },
@@ -183,6 +199,11 @@ impl From<(*mut u8, usize)> for &mut flat_binder_object {
     }
 }
 
+/// Get the (ptr, cookie) pair which identifies a local binder node in the driver.
+pub(crate) fn local_node_pointer(binder: &SIBinder) -> (binder_uintptr_t, binder_uintptr_t) {
+    split_fat_pointer(binder.as_raw())
+}
+
 pub(crate) fn raw_pointer_to_strong_binder(raw_pointer: (binder_uintptr_t, binder_uintptr_t)) -> ManuallyDrop<SIBinder> {
     assert!(raw_pointer.0 != 0, "raw_pointer_to_strong_binder(): raw_pointer is null");
     ManuallyDrop::new(SIBinder::from_raw(make_fat_pointer(raw_pointer)))
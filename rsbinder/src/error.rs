@@ -32,6 +32,7 @@ pub enum StatusCode {
     WouldBlock,
     TimedOut,
     BadFd,
+    FrozenObject,
     Errno(i32),
     ServiceSpecific(i32),
 }
@@ -61,6 +62,7 @@ impl fmt::Display for StatusCode {
             StatusCode::WouldBlock => write!(f, "WouldBlock"),
             StatusCode::TimedOut => write!(f, "TimedOut"),
             StatusCode::BadFd => write!(f, "BadFd"),
+            StatusCode::FrozenObject => write!(f, "FrozenObject"),
             StatusCode::Errno(errno) => write!(f, "Errno({errno})"),
             StatusCode::ServiceSpecific(v) => write!(f, "ServiceSpecific({v})"),
         }
@@ -90,6 +92,7 @@ impl From<StatusCode> for i32 {
             StatusCode::WouldBlock => -(nix::errno::Errno::EWOULDBLOCK as i32),
             StatusCode::TimedOut => -(nix::errno::Errno::ETIMEDOUT as i32),
             StatusCode::BadFd => -(nix::errno::Errno::EBADF as i32),
+            StatusCode::FrozenObject => UNKNOWN_ERROR + 9,
             StatusCode::ServiceSpecific(v) => v,
             StatusCode::Errno(errno) => errno,
         }
@@ -120,6 +123,7 @@ impl From<i32> for StatusCode {
             code if code == StatusCode::WouldBlock.into() => StatusCode::WouldBlock,
             code if code == StatusCode::TimedOut.into() => StatusCode::TimedOut,
             code if code == StatusCode::BadFd.into() => StatusCode::BadFd,
+            code if code == StatusCode::FrozenObject.into() => StatusCode::FrozenObject,
             code if code < 0 => StatusCode::Errno(code),
             _ => StatusCode::ServiceSpecific(code),
         }
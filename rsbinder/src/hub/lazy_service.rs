@@ -0,0 +1,390 @@
+// Copyright 2022 Jeff Kim <hiking90@gmail.com>
+// SPDX-License-Identifier: Apache-2.0
+
+//! Registration of lazy services which exit the process when they have no clients.
+//!
+//! The service manager tracks the clients of the services registered by
+//! [`LazyServiceRegistrar`] and notifies the registrar by `IClientCallback::onClients()`.
+//! When none of the services has clients, the registrar unregisters all of them with
+//! `tryUnregisterService()` and exits the process. The notifications are delivered to
+//! the binder thread pool, so it must be started.
+//!
+//! The process exits only after the service manager stops listing the services, so it
+//! keeps running with a service manager which doesn't support `tryUnregisterService()`.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, OnceLock};
+
+use crate::*;
+use super::{IServiceManager, IClientCallback, BnClientCallback};
+
+type ActiveServicesCallback = Arc<dyn Fn(bool) -> bool + Send + Sync>;
+
+struct RegisteredService {
+    binder: SIBinder,
+    allow_isolated: bool,
+    dump_priority: i32,
+    // Whether the service is registered to the service manager.
+    registered: bool,
+    // Whether the service has clients as notified by the service manager.
+    has_clients: bool,
+}
+
+#[derive(Default)]
+struct RegistrarState {
+    services: HashMap<String, RegisteredService>,
+    force_persist: bool,
+    // The last value reported to the active services callback.
+    has_clients: Option<bool>,
+    active_services_callback: Option<ActiveServicesCallback>,
+}
+
+// The calls to the service manager made by the registrar.
+trait ServiceRegistry: Send + Sync {
+    fn add_service(&self, name: &str, binder: &SIBinder, allow_isolated: bool, dump_priority: i32) -> status::Result<()>;
+    fn try_unregister_service(&self, name: &str, binder: &SIBinder) -> status::Result<()>;
+    // Whether `binder` is still listed under `name`.
+    fn is_registered(&self, name: &str, binder: &SIBinder) -> bool;
+}
+
+struct HubRegistry;
+
+impl ServiceRegistry for HubRegistry {
+    fn add_service(&self, name: &str, binder: &SIBinder, allow_isolated: bool, dump_priority: i32) -> status::Result<()> {
+        super::default().addService(name, binder, allow_isolated, dump_priority)
+    }
+
+    fn try_unregister_service(&self, name: &str, binder: &SIBinder) -> status::Result<()> {
+        super::default().tryUnregisterService(name, binder)
+    }
+
+    fn is_registered(&self, name: &str, binder: &SIBinder) -> bool {
+        match super::default().checkService(name) {
+            Ok(Some(registered)) => registered == *binder,
+            Ok(None) => false,
+            // Assume it is still registered not to exit the process by mistake.
+            Err(_) => true,
+        }
+    }
+}
+
+struct RegistrarInner {
+    state: Mutex<RegistrarState>,
+    registry: Box<dyn ServiceRegistry>,
+}
+
+impl RegistrarInner {
+    fn new(registry: Box<dyn ServiceRegistry>) -> Self {
+        Self {
+            state: Mutex::new(RegistrarState::default()),
+            registry,
+        }
+    }
+
+    fn try_unregister(&self, state: &mut RegistrarState) -> bool {
+        for (name, service) in state.services.iter_mut().filter(|(_, service)| service.registered) {
+            if let Err(err) = self.registry.try_unregister_service(name, &service.binder) {
+                log::info!("Failed to unregister service {}: {}", name, err);
+                return false;
+            }
+            // The service manager may accept the request without unregistering the service.
+            if self.registry.is_registered(name, &service.binder) {
+                log::info!("Service {} is still registered after tryUnregisterService()", name);
+                return false;
+            }
+            service.registered = false;
+        }
+        true
+    }
+
+    fn re_register(&self, state: &mut RegistrarState) {
+        for (name, service) in state.services.iter_mut().filter(|(_, service)| !service.registered) {
+            match self.registry.add_service(name, &service.binder, service.allow_isolated, service.dump_priority) {
+                Ok(_) => service.registered = true,
+                Err(err) => log::error!("Failed to re-register service {}: {}", name, err),
+            }
+        }
+    }
+
+    // Returns true if all services were unregistered and the process should exit.
+    fn maybe_try_shutdown(&self) -> bool {
+        let (has_clients, callback) = {
+            let mut state = self.state.lock().unwrap();
+            if state.force_persist {
+                return false;
+            }
+
+            let has_clients = state.services.values().any(|service| service.has_clients);
+            if state.has_clients == Some(has_clients) && state.active_services_callback.is_some() {
+                return false;
+            }
+            state.has_clients = Some(has_clients);
+            (has_clients, state.active_services_callback.clone())
+        };
+
+        // The callback may call try_unregister() or re_register(), so the lock is released.
+        let handled = callback.map(|callback| callback(has_clients)).unwrap_or(false);
+        if handled || has_clients {
+            return false;
+        }
+
+        let mut state = self.state.lock().unwrap();
+        // The state may be changed while the lock was released.
+        if state.force_persist || state.services.values().any(|service| service.has_clients) {
+            return false;
+        }
+
+        if self.try_unregister(&mut state) {
+            return true;
+        }
+
+        // Some clients got the services in the meantime.
+        self.re_register(&mut state);
+        false
+    }
+
+    fn shutdown_if_unused(&self) {
+        if self.maybe_try_shutdown() {
+            log::info!("Unregistered all lazy services. Exiting the process.");
+            std::process::exit(0);
+        }
+    }
+}
+
+// The IClientCallback registered to the service manager for each lazy service.
+struct ClientCounterCallback(Arc<RegistrarInner>);
+
+impl Interface for ClientCounterCallback {}
+
+impl IClientCallback for ClientCounterCallback {
+    fn onClients(&self, registered: &SIBinder, has_clients: bool) -> status::Result<()> {
+        {
+            let mut state = self.0.state.lock().unwrap();
+            match state.services.iter_mut().find(|(_, service)| service.binder == *registered) {
+                Some((name, service)) => {
+                    log::debug!("Service {} has clients: {}", name, has_clients);
+                    service.has_clients = has_clients;
+                }
+                None => {
+                    log::warn!("Got onClients() for an unknown service");
+                    return Err(ExceptionCode::IllegalArgument.into());
+                }
+            }
+        }
+
+        self.0.shutdown_if_unused();
+        Ok(())
+    }
+}
+
+/// Registrar of the lazy services of the process.
+///
+/// The process exits when none of the registered services has clients,
+/// unless [`force_persist()`](Self::force_persist) is set or the
+/// [active services callback](Self::set_active_services_callback) handles it.
+/// The services are registered to the service manager of the default ProcessState.
+pub struct LazyServiceRegistrar {
+    inner: Arc<RegistrarInner>,
+    callback: Strong<dyn IClientCallback>,
+}
+
+impl LazyServiceRegistrar {
+    /// Get the registrar of the process.
+    pub fn get() -> &'static LazyServiceRegistrar {
+        static REGISTRAR: OnceLock<LazyServiceRegistrar> = OnceLock::new();
+        REGISTRAR.get_or_init(|| {
+            let inner = Arc::new(RegistrarInner::new(Box::new(HubRegistry)));
+            let callback = BnClientCallback::new_binder(ClientCounterCallback(inner.clone()));
+            LazyServiceRegistrar { inner, callback }
+        })
+    }
+
+    /// Register a lazy service to the service manager.
+    pub fn register_service(&self, name: &str, binder: SIBinder, allow_isolated: bool,
+        dump_priority: i32) -> status::Result<()> {
+        self.inner.registry.add_service(name, &binder, allow_isolated, dump_priority)?;
+
+        let mut state = self.inner.state.lock().unwrap();
+        // The client callback is registered only once for each service.
+        if !state.services.contains_key(name) {
+            super::default().registerClientCallback(name, &binder, &self.callback)?;
+        }
+        state.services.insert(name.to_owned(), RegisteredService {
+            binder,
+            allow_isolated,
+            dump_priority,
+            registered: true,
+            has_clients: false,
+        });
+        Ok(())
+    }
+
+    /// Keep the process alive even if the services have no clients.
+    /// When it is cleared, the process may exit if the services have no clients.
+    pub fn force_persist(&self, persist: bool) {
+        let has_clients = {
+            let mut state = self.inner.state.lock().unwrap();
+            state.force_persist = persist;
+            state.services.values().any(|service| service.has_clients)
+        };
+
+        if !persist && !has_clients {
+            self.inner.shutdown_if_unused();
+        }
+    }
+
+    /// Set a callback which is called when the services get clients or lose all clients.
+    ///
+    /// The argument of the callback is whether any of the services has clients. If the
+    /// callback returns true, the registrar neither unregisters the services nor exits the
+    /// process, and the callback is responsible to call [`try_unregister()`](Self::try_unregister)
+    /// and [`re_register()`](Self::re_register).
+    pub fn set_active_services_callback(&self, callback: impl Fn(bool) -> bool + Send + Sync + 'static) {
+        let mut state = self.inner.state.lock().unwrap();
+        state.active_services_callback = Some(Arc::new(callback));
+        state.has_clients = None;
+    }
+
+    /// Try to unregister all services from the service manager.
+    /// It fails if any service has clients, and the services unregistered so far stay
+    /// unregistered until [`re_register()`](Self::re_register) is called.
+    pub fn try_unregister(&self) -> bool {
+        self.inner.try_unregister(&mut self.inner.state.lock().unwrap())
+    }
+
+    /// Register again the services unregistered by [`try_unregister()`](Self::try_unregister).
+    pub fn re_register(&self) {
+        self.inner.re_register(&mut self.inner.state.lock().unwrap())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Dummy;
+
+    impl Remotable for Dummy {
+        fn descriptor() -> &'static str {
+            "test.lazy.IDummy"
+        }
+
+        fn on_transact(&self, _code: TransactionCode, _reader: &mut Parcel, _reply: &mut Parcel) -> Result<()> {
+            Ok(())
+        }
+
+        fn on_dump(&self, _writer: &mut dyn std::io::Write, _args: &[String]) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    // A service manager which accepts tryUnregisterService() without unregistering the
+    // service, if `ignore_unregister` is set.
+    #[derive(Default)]
+    struct MockRegistry {
+        services: Mutex<HashMap<String, SIBinder>>,
+        ignore_unregister: bool,
+    }
+
+    impl ServiceRegistry for Arc<MockRegistry> {
+        fn add_service(&self, name: &str, binder: &SIBinder, _allow_isolated: bool, _dump_priority: i32) -> status::Result<()> {
+            self.services.lock().unwrap().insert(name.to_owned(), binder.clone());
+            Ok(())
+        }
+
+        fn try_unregister_service(&self, name: &str, _binder: &SIBinder) -> status::Result<()> {
+            if !self.ignore_unregister {
+                self.services.lock().unwrap().remove(name);
+            }
+            Ok(())
+        }
+
+        fn is_registered(&self, name: &str, binder: &SIBinder) -> bool {
+            self.services.lock().unwrap().get(name) == Some(binder)
+        }
+    }
+
+    fn registrar(registry: &Arc<MockRegistry>, names: &[&str]) -> RegistrarInner {
+        let inner = RegistrarInner::new(Box::new(registry.clone()));
+        for name in names {
+            let binder = Binder::new(Dummy).as_binder();
+            registry.add_service(name, &binder, false, 0).unwrap();
+            inner.state.lock().unwrap().services.insert(name.to_string(), RegisteredService {
+                binder,
+                allow_isolated: false,
+                dump_priority: 0,
+                registered: true,
+                has_clients: true,
+            });
+        }
+        inner
+    }
+
+    fn set_has_clients(inner: &RegistrarInner, name: &str, has_clients: bool) {
+        inner.state.lock().unwrap().services.get_mut(name).unwrap().has_clients = has_clients;
+    }
+
+    #[test]
+    fn test_shutdown_without_clients() {
+        let registry = Arc::new(MockRegistry::default());
+        let inner = registrar(&registry, &["a", "b"]);
+
+        assert!(!inner.maybe_try_shutdown());
+        set_has_clients(&inner, "a", false);
+        assert!(!inner.maybe_try_shutdown());
+        assert_eq!(registry.services.lock().unwrap().len(), 2);
+
+        set_has_clients(&inner, "b", false);
+        assert!(inner.maybe_try_shutdown());
+        assert!(registry.services.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_shutdown_force_persist() {
+        let registry = Arc::new(MockRegistry::default());
+        let inner = registrar(&registry, &["a"]);
+        inner.state.lock().unwrap().force_persist = true;
+
+        set_has_clients(&inner, "a", false);
+        assert!(!inner.maybe_try_shutdown());
+        assert_eq!(registry.services.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_shutdown_ignored_unregister() {
+        let registry = Arc::new(MockRegistry { ignore_unregister: true, ..Default::default() });
+        let inner = registrar(&registry, &["a"]);
+
+        // The process keeps running while the service is still registered.
+        set_has_clients(&inner, "a", false);
+        assert!(!inner.maybe_try_shutdown());
+        assert!(inner.state.lock().unwrap().services["a"].registered);
+        assert_eq!(registry.services.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_active_services_callback() {
+        let registry = Arc::new(MockRegistry::default());
+        let inner = registrar(&registry, &["a"]);
+        let reported = Arc::new(Mutex::new(Vec::new()));
+        let callback_reported = reported.clone();
+        inner.state.lock().unwrap().active_services_callback = Some(Arc::new(move |has_clients| {
+            callback_reported.lock().unwrap().push(has_clients);
+            true
+        }));
+
+        assert!(!inner.maybe_try_shutdown());
+        // The same state is reported only once.
+        assert!(!inner.maybe_try_shutdown());
+        set_has_clients(&inner, "a", false);
+        assert!(!inner.maybe_try_shutdown());
+        assert_eq!(*reported.lock().unwrap(), vec![true, false]);
+
+        // The callback handled it, so the service is still registered.
+        assert_eq!(registry.services.lock().unwrap().len(), 1);
+        assert!(inner.try_unregister(&mut inner.state.lock().unwrap()));
+        assert!(registry.services.lock().unwrap().is_empty());
+        inner.re_register(&mut inner.state.lock().unwrap());
+        assert_eq!(registry.services.lock().unwrap().len(), 1);
+    }
+}
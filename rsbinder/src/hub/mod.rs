@@ -1,5 +1,13 @@
 // Copyright 2022 Jeff Kim <hiking90@gmail.com>
 // SPDX-License-Identifier: Apache-2.0
 
+//! The clients of the service manager.
+//!
+//! The functions of this module and [`LazyServiceRegistrar`] use the service manager
+//! of the default ProcessState. For a ProcessState built by `ProcessState::builder()`,
+//! call the methods of [`IServiceManager`] on the service manager from [`for_process()`].
+
 mod servicemanager;
-pub use servicemanager::*;
\ No newline at end of file
+mod lazy_service;
+pub use servicemanager::*;
+pub use lazy_service::LazyServiceRegistrar;
\ No newline at end of file
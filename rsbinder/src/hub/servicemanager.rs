@@ -3,8 +3,10 @@
 
 include!(concat!(env!("OUT_DIR"), "/service_manager.rs"));
 
-use std::sync::{Arc, Once};
+use std::sync::{Arc, Mutex, Once};
 use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{channel, Sender};
+use std::time::{Duration, Instant};
 
 use crate::*;
 pub use android::os::IServiceManager::{
@@ -21,6 +23,12 @@ pub use android::os::IServiceCallback::{
     IServiceCallback, BnServiceCallback,
 };
 
+pub use android::os::IClientCallback::{
+    IClientCallback, BnClientCallback,
+};
+
+pub use android::os::ServiceDebugInfo::ServiceDebugInfo;
+
 static INIT: Once = Once::new();
 static mut GLOBAL_SM: Option<Arc<BpServiceManager>> = None;  // Assume SM is i32 for simplicity
 static IS_INIT: AtomicBool = AtomicBool::new(false);
@@ -44,16 +52,22 @@ pub fn default() -> Arc<BpServiceManager> {
     }
 }
 
+/// Retrieve the service manager of the binder device opened by `process`.
+/// Use it instead of default() to talk to a ProcessState other than the default one.
+pub fn for_process(process: &ProcessState) -> Result<Arc<BpServiceManager>> {
+    let service_manager = process.context_object()?;
+    let service_manager = BpServiceManager::from_binder(service_manager)
+        .ok_or(StatusCode::BadType)?;
+    Ok(Arc::new(service_manager))
+}
+
 /// Retrieve an existing service, blocking for a few seconds if it doesn't yet
 /// exist.
+///
+/// It waits only for the services declared on the device, see is_declared(),
+/// which the service manager may start on demand.
 pub fn get_service(name: &str) -> Option<SIBinder> {
-    match default().getService(name) {
-        Ok(result) => result,
-        Err(err) => {
-            log::error!("Failed to get service {}: {:?}", name, err);
-            None
-        }
-    }
+    get_service_of(&*default(), name)
 }
 
 /// Retrieve an existing service called @a name from the service
@@ -80,6 +94,17 @@ pub fn list_services(dump_priority: i32) -> Vec<String> {
     }
 }
 
+/// Return the name and the pid of the process of all registered services.
+pub fn get_service_debug_info() -> Vec<ServiceDebugInfo> {
+    match default().getServiceDebugInfo() {
+        Ok(result) => result,
+        Err(err) => {
+            log::error!("Failed to get service debug info: {}", err);
+            Vec::new()
+        }
+    }
+}
+
 pub fn add_service(identifier: &str, binder: SIBinder) -> std::result::Result<(), Status> {
     default().addService(identifier, &binder, false, DUMP_FLAG_PRIORITY_DEFAULT)
 }
@@ -107,6 +132,29 @@ pub fn is_declared(name: &str) -> bool {
     }
 }
 
+/// Returns the instances declared for an interface, e.g. `["default"]` for
+/// `android.hardware.usb.IUsb`.
+pub fn get_declared_instances(interface: &str) -> Vec<String> {
+    match default().getDeclaredInstances(interface) {
+        Ok(result) => result,
+        Err(err) => {
+            log::error!("Failed to get_declared_instances({}): {}", interface, err);
+            Vec::new()
+        }
+    }
+}
+
+/// Returns the name of the APEX which can update a declared service, if any.
+pub fn updatable_via_apex(name: &str) -> Option<String> {
+    match default().updatableViaApex(name) {
+        Ok(result) => result,
+        Err(err) => {
+            log::error!("Failed to updatable_via_apex({}): {}", name, err);
+            None
+        }
+    }
+}
+
 pub fn get_interface<T: FromIBinder + ?Sized>(name: &str) -> Result<Strong<T>> {
     let service = get_service(name);
     match service {
@@ -115,11 +163,134 @@ pub fn get_interface<T: FromIBinder + ?Sized>(name: &str) -> Result<Strong<T>> {
     }
 }
 
+// The interval to check the service again in case the notification can't be delivered,
+// e.g. when the binder thread pool is not started.
+const WAIT_FOR_SERVICE_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+// How long get_service() waits for a declared service to be started.
+const GET_SERVICE_TIMEOUT: Duration = Duration::from_secs(5);
+
+// The IServiceCallback which forwards the registered service to wait_for_service().
+struct WaitForServiceCallback(Mutex<Sender<SIBinder>>);
+
+impl Interface for WaitForServiceCallback {}
+
+impl IServiceCallback for WaitForServiceCallback {
+    fn onRegistration(&self, _name: &str, service: &SIBinder) -> status::Result<()> {
+        let _ = self.0.lock().unwrap().send(service.clone());
+        Ok(())
+    }
+}
+
+/// Retrieve a service, waiting until it is registered.
+///
+/// The service manager is asked for the service once by getService(), which starts
+/// the service on demand if it is declared by the service manager, e.g. in the service
+/// manifest of rsb_hub.
+///
+/// It waits forever if `timeout` is None, otherwise returns `StatusCode::TimedOut`
+/// when the service is not registered within `timeout`. The registration is notified
+/// to the binder thread pool, and the service is also checked periodically so that it
+/// works without the thread pool.
+pub fn wait_for_service(name: &str, timeout: Option<Duration>) -> Result<SIBinder> {
+    wait_for_service_of(&*default(), name, timeout)
+}
+
+fn get_service_of(sm: &dyn IServiceManager, name: &str) -> Option<SIBinder> {
+    match sm.getService(name) {
+        Ok(Some(service)) => return Some(service),
+        Ok(None) => {}
+        Err(err) => {
+            log::error!("Failed to get service {}: {:?}", name, err);
+            return None;
+        }
+    }
+
+    match sm.isDeclared(name) {
+        Ok(true) => {}
+        Ok(false) => return None,
+        Err(err) => {
+            log::error!("Failed to is_declared({}): {}", name, err);
+            return None;
+        }
+    }
+
+    // getService() above started the declared service if it is started on demand.
+    match wait_for_registration(sm, name, Some(GET_SERVICE_TIMEOUT)) {
+        Ok(service) => Some(service),
+        Err(err) => {
+            log::warn!("Declared service {} is not registered in {:?}: {}", name, GET_SERVICE_TIMEOUT, err);
+            None
+        }
+    }
+}
+
+fn wait_for_service_of(sm: &dyn IServiceManager, name: &str, timeout: Option<Duration>) -> Result<SIBinder> {
+    // Unlike checkService(), getService() starts the service on demand.
+    match sm.getService(name) {
+        Ok(Some(service)) => return Ok(service),
+        Ok(None) => {}
+        Err(err) => log::warn!("Failed to get service {}: {:?}", name, err),
+    }
+
+    wait_for_registration(sm, name, timeout)
+}
+
+fn wait_for_registration(sm: &dyn IServiceManager, name: &str, timeout: Option<Duration>) -> Result<SIBinder> {
+    let deadline = timeout.map(|timeout| Instant::now() + timeout);
+
+    let (sender, receiver) = channel();
+    let callback = BnServiceCallback::new_binder(WaitForServiceCallback(Mutex::new(sender)));
+    let registered = match sm.registerForNotifications(name, &callback) {
+        Ok(_) => true,
+        Err(err) => {
+            log::warn!("Failed to register for notifications of {}: {}", name, err);
+            false
+        }
+    };
+
+    let result = loop {
+        if let Ok(Some(service)) = sm.checkService(name) {
+            break Ok(service);
+        }
+
+        let interval = match deadline {
+            Some(deadline) => {
+                let remaining = deadline.saturating_duration_since(Instant::now());
+                if remaining.is_zero() {
+                    break Err(StatusCode::TimedOut);
+                }
+                remaining.min(WAIT_FOR_SERVICE_POLL_INTERVAL)
+            }
+            None => WAIT_FOR_SERVICE_POLL_INTERVAL,
+        };
+
+        if let Ok(service) = receiver.recv_timeout(interval) {
+            break Ok(service);
+        }
+    };
+
+    if registered {
+        if let Err(err) = sm.unregisterForNotifications(name, &callback) {
+            log::warn!("Failed to unregister for notifications of {}: {}", name, err);
+        }
+    }
+
+    result
+}
+
+/// Retrieve a service for a particular interface, waiting until it is registered.
+/// See wait_for_service() for `timeout`.
+pub fn wait_for_interface<T: FromIBinder + ?Sized>(name: &str, timeout: Option<Duration>) -> Result<Strong<T>> {
+    FromIBinder::try_from(wait_for_service(name, timeout)?)
+}
+
 #[cfg(test)]
 mod tests {
     #![allow(non_snake_case)]
 
     use super::*;
+    use std::collections::HashMap;
     use std::sync::OnceLock;
 
     fn setup() {
@@ -185,7 +356,115 @@ mod tests {
         setup();
 
         assert!(!is_declared("android.hardware.usb.IUsb/default"));
+        assert!(get_declared_instances("android.hardware.usb.IUsb").is_empty());
+        assert!(updatable_via_apex("android.hardware.usb.IUsb/default").is_none());
+
+        let result = wait_for_service("unknown_service", Some(Duration::from_millis(100)));
+        assert_eq!(result.err(), Some(StatusCode::TimedOut));
 
         Ok(())
     }
+
+    struct Dummy;
+    impl crate::Interface for Dummy {}
+    impl crate::Remotable for Dummy {
+        fn descriptor() -> &'static str where Self: Sized { "Dummy" }
+        fn on_transact(&self, _code: crate::TransactionCode, _reader: &mut crate::Parcel, _writer: &mut crate::Parcel) -> crate::Result<()> {
+            Ok(())
+        }
+        fn on_dump(&self, _writer: &mut dyn std::io::Write, _args: &[String]) -> crate::Result<()> {
+            Ok(())
+        }
+    }
+
+    // A service manager which starts the declared services on getService() like rsb_hub.
+    // A started service is registered by another thread after a while.
+    #[derive(Default)]
+    struct MockServiceManager {
+        declared: Vec<String>,
+        services: Arc<Mutex<HashMap<String, SIBinder>>>,
+        callbacks: Arc<Mutex<Vec<(String, Strong<dyn IServiceCallback>)>>>,
+        started: Mutex<Vec<String>>,
+    }
+
+    impl crate::Interface for MockServiceManager {}
+
+    impl IServiceManager for MockServiceManager {
+        fn getService(&self, name: &str) -> status::Result<Option<SIBinder>> {
+            if let Some(service) = self.checkService(name)? {
+                return Ok(Some(service));
+            }
+            if self.declared.iter().any(|declared| declared == name) {
+                self.started.lock().unwrap().push(name.to_owned());
+                let (name, services, callbacks) = (name.to_owned(), self.services.clone(), self.callbacks.clone());
+                std::thread::spawn(move || {
+                    std::thread::sleep(Duration::from_millis(100));
+                    let service = crate::Binder::new(Dummy).as_binder();
+                    services.lock().unwrap().insert(name.clone(), service.clone());
+                    for (_, callback) in callbacks.lock().unwrap().iter().filter(|(n, _)| *n == name) {
+                        callback.onRegistration(&name, &service).unwrap();
+                    }
+                });
+            }
+            Ok(None)
+        }
+        fn checkService(&self, name: &str) -> status::Result<Option<SIBinder>> {
+            Ok(self.services.lock().unwrap().get(name).cloned())
+        }
+        fn addService(&self, _name: &str, _service: &SIBinder, _allow_isolated: bool, _dump_priority: i32) -> status::Result<()> {
+            unimplemented!()
+        }
+        fn listServices(&self, _dump_priority: i32) -> status::Result<Vec<String>> {
+            unimplemented!()
+        }
+        fn registerForNotifications(&self, name: &str, callback: &Strong<dyn IServiceCallback>) -> status::Result<()> {
+            self.callbacks.lock().unwrap().push((name.to_owned(), callback.clone()));
+            Ok(())
+        }
+        fn unregisterForNotifications(&self, _name: &str, callback: &Strong<dyn IServiceCallback>) -> status::Result<()> {
+            self.callbacks.lock().unwrap().retain(|(_, registered)| registered.as_binder() != callback.as_binder());
+            Ok(())
+        }
+        fn isDeclared(&self, name: &str) -> status::Result<bool> {
+            Ok(self.declared.iter().any(|declared| declared == name))
+        }
+        fn getDeclaredInstances(&self, _iface: &str) -> status::Result<Vec<String>> {
+            unimplemented!()
+        }
+        fn updatableViaApex(&self, _name: &str) -> status::Result<Option<String>> {
+            unimplemented!()
+        }
+        fn getConnectionInfo(&self, _name: &str) -> status::Result<Option<android::os::ConnectionInfo::ConnectionInfo>> {
+            unimplemented!()
+        }
+        fn registerClientCallback(&self, _name: &str, _service: &SIBinder, _callback: &Strong<dyn IClientCallback>) -> status::Result<()> {
+            unimplemented!()
+        }
+        fn tryUnregisterService(&self, _name: &str, _service: &SIBinder) -> status::Result<()> {
+            unimplemented!()
+        }
+        fn getServiceDebugInfo(&self) -> status::Result<Vec<ServiceDebugInfo>> {
+            unimplemented!()
+        }
+    }
+
+    #[test]
+    fn test_start_on_demand() {
+        let sm = MockServiceManager {
+            declared: vec!["lazy.a".to_owned(), "lazy.b".to_owned()],
+            ..Default::default()
+        };
+
+        // wait_for_service() asks for the service to start it, and waits for the registration.
+        let service = wait_for_service_of(&sm, "lazy.a", Some(Duration::from_secs(10))).unwrap();
+        assert_eq!(sm.checkService("lazy.a").unwrap(), Some(service));
+        assert!(sm.callbacks.lock().unwrap().is_empty());
+
+        // get_service() waits only for a declared service.
+        assert!(get_service_of(&sm, "lazy.b").is_some());
+        assert!(get_service_of(&sm, "unknown").is_none());
+        assert_eq!(*sm.started.lock().unwrap(), vec!["lazy.a".to_owned(), "lazy.b".to_owned()]);
+
+        assert_eq!(wait_for_service_of(&sm, "unknown", Some(Duration::from_millis(100))).err(), Some(StatusCode::TimedOut));
+    }
 }
@@ -0,0 +1,89 @@
+// Copyright 2022 Jeff Kim <hiking90@gmail.com>
+// SPDX-License-Identifier: Apache-2.0
+
+//! Interception of the incoming transactions of local binders.
+//!
+//! Interceptors see each user transaction before it is dispatched to the service and
+//! can reject it with a [`Status`](crate::Status). The global interceptors registered by
+//! [`add_transaction_interceptor`] run first, then the ones added by
+//! [`Binder::add_interceptor`](crate::Binder::add_interceptor), in the order of registration.
+
+use std::sync::{Arc, RwLock};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use crate::{
+    binder::{TransactionCode, TransactionFlags},
+    parcel::Parcel,
+    status,
+    thread_state::{self, CallingContext},
+};
+
+/// An incoming transaction seen by interceptors.
+#[derive(Debug, Clone)]
+pub struct InterceptedTransaction<'a> {
+    pub descriptor: &'a str,
+    pub code: TransactionCode,
+    pub flags: TransactionFlags,
+    pub caller: CallingContext,
+}
+
+/// Interceptor of the incoming transactions.
+pub trait Interceptor: Send + Sync {
+    /// Inspect a transaction before it is dispatched.
+    ///
+    /// `data` is positioned after the interface token and the position is restored
+    /// after this call. Returning an error rejects the transaction, and the status
+    /// is sent to the caller as the result of the transaction.
+    fn intercept(&self, transaction: &InterceptedTransaction, data: &mut Parcel) -> status::Result<()>;
+}
+
+static INTERCEPTORS: RwLock<Vec<Arc<dyn Interceptor>>> = RwLock::new(Vec::new());
+// To skip the interception when there is no global interceptor.
+static HAS_INTERCEPTORS: AtomicBool = AtomicBool::new(false);
+
+/// Register an interceptor of the transactions to all local binders of the process.
+pub fn add_transaction_interceptor(interceptor: Arc<dyn Interceptor>) {
+    let mut interceptors = INTERCEPTORS.write().unwrap();
+    interceptors.push(interceptor);
+    HAS_INTERCEPTORS.store(true, Ordering::Release);
+}
+
+/// Unregister an interceptor registered by add_transaction_interceptor().
+/// Returns false if the interceptor was not registered.
+pub fn remove_transaction_interceptor(interceptor: &Arc<dyn Interceptor>) -> bool {
+    let mut interceptors = INTERCEPTORS.write().unwrap();
+    let len = interceptors.len();
+    interceptors.retain(|i| !Arc::ptr_eq(i, interceptor));
+    HAS_INTERCEPTORS.store(!interceptors.is_empty(), Ordering::Release);
+    len != interceptors.len()
+}
+
+// Run the global interceptors and then `local` interceptors until one of them rejects the transaction.
+pub(crate) fn intercept(local: &[Arc<dyn Interceptor>], descriptor: &str, code: TransactionCode,
+    data: &mut Parcel) -> status::Result<()> {
+    let has_global = HAS_INTERCEPTORS.load(Ordering::Acquire);
+    if !has_global && local.is_empty() {
+        return Ok(());
+    }
+
+    let transaction = InterceptedTransaction {
+        descriptor,
+        code,
+        flags: thread_state::last_transaction_binder_flags(),
+        caller: thread_state::calling_context(),
+    };
+
+    let position = data.data_position();
+    let global = if has_global {
+        INTERCEPTORS.read().unwrap().clone()
+    } else {
+        Vec::new()
+    };
+
+    for interceptor in global.iter().chain(local.iter()) {
+        let result = interceptor.intercept(&transaction, data);
+        data.set_data_position(position);
+        result?;
+    }
+    Ok(())
+}
@@ -26,6 +26,10 @@ pub mod binderfs;
 pub mod parcelable;
 pub mod proxy;
 pub mod file_descriptor;
+pub mod recorded_transaction;
+pub mod observer;
+pub mod stats;
+pub mod interceptor;
 pub mod parcelable_holder;
 pub mod error;
 mod ref_counter;
@@ -36,7 +40,8 @@ pub mod hub;
 #[cfg(feature = "async")]
 mod rt;
 
-pub use process_state::ProcessState;
+pub use process_state::{ProcessState, ProcessStateBuilder, CallRestriction, FrozenInfo,
+    NodeRefInfo, NodeDebugInfo, Nodes, Poller};
 pub use parcel::Parcel;
 pub use status::{ExceptionCode, Status};
 pub use error::{Result, StatusCode};
@@ -45,10 +50,19 @@ pub use proxy::*;
 pub use native::*;
 pub use parcelable::*;
 pub use file_descriptor::ParcelFileDescriptor;
+pub use recorded_transaction::RecordedTransaction;
+pub use observer::{TransactionObserver, TransactionEvent, TransactionDirection,
+    add_transaction_observer, remove_transaction_observer};
+pub use interceptor::{Interceptor, InterceptedTransaction, add_transaction_interceptor,
+    remove_transaction_interceptor};
 pub use parcelable_holder::{ParcelableHolder, ParcelableMetadata};
+pub use thread_state::{CallingContext, calling_context, clear_calling_identity, restore_calling_identity,
+    UNSET_WORK_SOURCE, calling_work_source_uid, set_calling_work_source_uid, clear_calling_work_source,
+    restore_calling_work_source, should_propagate_work_source};
+#[cfg(feature = "async")]
+pub use binder_async::{BinderAsyncPool, BinderAsyncRuntime, BoxFuture, OnewayQueue,
+    DEFAULT_MAX_ONEWAY_TASKS, set_max_oneway_tasks};
 #[cfg(feature = "async")]
-pub use binder_async::{BinderAsyncPool, BinderAsyncRuntime, BoxFuture};
-#[cfg(feature = "tokio")]
 pub use rt::*;
 
 pub const DEFAULT_BINDER_CONTROL_PATH: &str = "/dev/binderfs/binder-control";
@@ -84,4 +98,34 @@ mod tests {
     fn process_state() {
         ProcessState::init("/dev/binderfs/binder", 0);
     }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn process_state_builder_error() {
+        let res = ProcessState::builder()
+            .driver("/dev/binderfs/not_existing_binder")
+            .max_threads(32)
+            .init();
+        assert!(res.is_err());
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn process_state_builder_already_initialized() {
+        ProcessState::init_default();
+        let res = ProcessState::builder()
+            .max_threads(8)
+            .init();
+        assert!(res.is_err());
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn calling_context_of_local_process() {
+        // It doesn't need ProcessState, and it is the local process out of a transaction.
+        let context = calling_context();
+        assert_eq!(context.pid, std::process::id() as i32);
+        assert_eq!(context.uid, nix::unistd::geteuid().as_raw());
+        assert_eq!(context.sid, None);
+    }
 }
@@ -38,6 +38,11 @@ macro_rules! __declare_binder_interface {
             pub trait $native_adapter {
                 fn as_sync(&self) -> &dyn $interface;
                 fn as_async(&self) -> &dyn $native_async;
+                /// Hand off a oneway transaction to the async runtime.
+                /// Returns false if the transaction must be handled by on_transact.
+                fn handle_oneway(&self, _code: $crate::TransactionCode, _reader: &mut $crate::Parcel) -> $crate::Result<bool> {
+                    Ok(false)
+                }
             }
 
             pub struct $native(Box<dyn $native_adapter + Send + Sync + 'static>);
@@ -68,12 +73,20 @@ macro_rules! __declare_binder_interface {
                 }
 
                 fn on_transact(&self, code: $crate::TransactionCode, reader: &mut $crate::Parcel, reply: &mut $crate::Parcel) -> $crate::Result<()> {
+                    if self.0.handle_oneway(code, reader)? {
+                        return Ok(());
+                    }
                     $on_transact(self.0.as_sync(), code, reader, reply)
                 }
 
                 fn on_dump(&self, _writer: &mut dyn std::io::Write, _args: &[String]) -> $crate::Result<()> {
                     self.0.as_sync().dump(_writer, _args)
                 }
+
+                fn on_shell_command(&self, _stdin: &mut dyn std::io::Read, _stdout: &mut dyn std::io::Write,
+                    _stderr: &mut dyn std::io::Write, _args: &[String]) -> $crate::Result<()> {
+                    self.0.as_sync().handle_shell_command(_stdin, _stdout, _stderr, _args)
+                }
             }
         )?
 
@@ -173,6 +186,11 @@ macro_rules! __declare_binder_interface {
             fn on_dump(&self, _writer: &mut dyn std::io::Write, _args: &[String]) -> $crate::Result<()> {
                 self.0.dump(_writer, _args)
             }
+
+            fn on_shell_command(&self, _stdin: &mut dyn std::io::Read, _stdout: &mut dyn std::io::Write,
+                _stderr: &mut dyn std::io::Write, _args: &[String]) -> $crate::Result<()> {
+                self.0.handle_shell_command(_stdin, _stdout, _stderr, _args)
+            }
         }
     };
 }
@@ -695,6 +713,11 @@ mod tests {
             fn dump(&self, _writer: &mut dyn std::io::Write, _args: &[String]) -> Result<()> {
                 self.inner.dump(_writer, _args)
             }
+
+            fn handle_shell_command(&self, _stdin: &mut dyn std::io::Read, _stdout: &mut dyn std::io::Write,
+                _stderr: &mut dyn std::io::Write, _args: &[String]) -> Result<()> {
+                self.inner.handle_shell_command(_stdin, _stdout, _stderr, _args)
+            }
         }
 
         impl<T, R> IEcho for Wrapper<T, R>
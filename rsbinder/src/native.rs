@@ -17,13 +17,15 @@
  * limitations under the License.
  */
 
-use std::sync::Arc;
+use std::sync::{Arc, Mutex, RwLock};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::ops::{Deref, DerefMut};
 use std::any::Any;
 use std::convert::TryFrom;
 use std::fs::File;
-use std::os::fd::FromRawFd;
+use std::os::fd::{FromRawFd, OwnedFd};
 use std::mem::ManuallyDrop;
+use std::time::SystemTime;
 
 use crate::{
     binder::*,
@@ -31,11 +33,21 @@ use crate::{
     error::*,
     thread_state,
     ref_counter::RefCounter,
+    file_descriptor::ParcelFileDescriptor,
+    recorded_transaction::RecordedTransaction,
+    observer::TransactionDirection,
+    stats,
+    interceptor::{self, Interceptor},
 };
 
 struct Inner<T: Remotable + Send + Sync> {
     remotable: T,
     _stability: Stability,
+    requesting_sid: AtomicBool,
+    extension: RwLock<Option<SIBinder>>,
+    interceptors: RwLock<Vec<Arc<dyn Interceptor>>>,
+    // The file to record the incoming transactions.
+    recording: Mutex<Option<File>>,
     strong: RefCounter,
     weak: RefCounter,
 }
@@ -65,11 +77,52 @@ impl<T: Remotable> Inner<T> {
                     ManuallyDrop::new(File::from_raw_fd(fd as _))
                 };
 
-                self.remotable.on_dump(file.deref_mut(), argv.as_slice())
+                self.remotable.on_dump(file.deref_mut(), argv.as_slice())?;
+
+                stats::write_stats(file.deref_mut(), T::descriptor(), TransactionDirection::Incoming)
+                    .map_err(|err| {
+                        log::error!("Failed to write the transaction stats: {}", err);
+                        StatusCode::from(err)
+                    })
             }
             SHELL_COMMAND_TRANSACTION => {
-                log::error!("SHELL_COMMAND_TRANSACTION is not supported.");
-                Err(StatusCode::InvalidOperation)
+                let mut fds = [0; 3];
+                for fd in fds.iter_mut() {
+                    let obj = _reader.read_object(true)?;
+                    if obj.header_type() != crate::sys::BINDER_TYPE_FD {
+                        return Err(StatusCode::BadType);
+                    }
+                    *fd = obj.handle();
+                }
+
+                let argc = _reader.read::<i32>()?;
+                let mut argv = Vec::new();
+                for _ in 0..argc {
+                    argv.push(_reader.read::<String>()?);
+                }
+
+                // IShellCallback is not supported, so it is ignored.
+                let _shell_callback = _reader.read::<Option<SIBinder>>()?;
+                let result_receiver = _reader.read::<Option<SIBinder>>()?;
+
+                let [mut stdin, mut stdout, mut stderr] = fds.map(|fd| unsafe {
+                    ManuallyDrop::new(File::from_raw_fd(fd as _))
+                });
+
+                let result = self.remotable.on_shell_command(stdin.deref_mut(), stdout.deref_mut(),
+                    stderr.deref_mut(), argv.as_slice());
+
+                if let Some(result_receiver) = result_receiver {
+                    let code = match result {
+                        Ok(_) => StatusCode::Ok.into(),
+                        Err(err) => err.into(),
+                    };
+                    send_shell_command_result(&result_receiver, code)?;
+                }
+
+                // The result of the command is delivered by the result receiver,
+                // so the transaction itself succeeds as Android does.
+                Ok(())
             }
             SYSPROPS_TRANSACTION => {
                 log::error!("SYSPROPS_TRANSACTION is not supported.");
@@ -78,6 +131,42 @@ impl<T: Remotable> Inner<T> {
             _ => Err(StatusCode::UnknownTransaction),
         }
     }
+
+    // Only the root and the owner of this process can record the transactions.
+    fn check_recording_permission() -> Result<()> {
+        let uid = thread_state::calling_context().uid;
+        if uid != 0 && uid != nix::unistd::geteuid().as_raw() {
+            log::error!("Calling uid {} is not allowed to record the transactions.", uid);
+            return Err(StatusCode::PermissionDenied);
+        }
+        Ok(())
+    }
+
+    fn record_transaction(&self, code: TransactionCode, reader: &Parcel, reply: &Parcel, result: &Result<()>) {
+        let mut recording = self.recording.lock().unwrap();
+        let Some(file) = recording.as_mut() else {
+            return;
+        };
+
+        let transaction = RecordedTransaction {
+            interface_name: T::descriptor().to_owned(),
+            code,
+            flags: thread_state::last_transaction_binder_flags(),
+            returned_status: match result {
+                Ok(_) => StatusCode::Ok.into(),
+                Err(err) => (*err).into(),
+            },
+            version: 0,
+            timestamp: SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap_or_default(),
+            data: reader.as_slice().to_vec(),
+            reply: reply.as_slice().to_vec(),
+        };
+
+        if let Err(err) = transaction.write_to(file) {
+            log::error!("Failed to record the transaction. The recording is stopped: {:?}", err);
+            *recording = None;
+        }
+    }
 }
 
 impl<T: 'static +  Remotable> IBinder for Inner<T> {
@@ -120,6 +209,14 @@ impl<T: 'static +  Remotable> IBinder for Inner<T> {
         false
     }
 
+    fn is_requesting_sid(&self) -> bool {
+        self.requesting_sid.load(Ordering::Relaxed)
+    }
+
+    fn get_extension(&self) -> Result<Option<SIBinder>> {
+        Ok(self.extension.read().unwrap().clone())
+    }
+
     fn inc_strong(&self, _strong: &SIBinder) -> Result<()> {
         self.strong.inc(|| { Ok(()) })
     }
@@ -155,20 +252,20 @@ impl<T: Remotable> Transactable for Inner<T> {
                 Ok(())
             }
             EXTENSION_TRANSACTION => {
-                log::error!("EXTENSION_TRANSACTION is not supported.");
-                Err(StatusCode::InvalidOperation)
-                // CHECK(reply != nullptr);
-                // err = reply->writeStrongBinder(getExtension());
+                reply.write(&*self.extension.read().unwrap())
             }
 
             STOP_RECORDING_TRANSACTION => {
-                log::error!("STOP_RECORDING_TRANSACTION is not supported.");
-                Err(StatusCode::InvalidOperation)
+                Self::check_recording_permission()?;
+                *self.recording.lock().unwrap() = None;
+                Ok(())
             }
 
             START_RECORDING_TRANSACTION => {
-                log::error!("START_RECORDING_TRANSACTION is not supported.");
-                Err(StatusCode::InvalidOperation)
+                Self::check_recording_permission()?;
+                let fd: ParcelFileDescriptor = reader.read()?;
+                *self.recording.lock().unwrap() = Some(File::from(OwnedFd::from(fd)));
+                Ok(())
             }
 
             DEBUG_PID_TRANSACTION => {
@@ -182,7 +279,16 @@ impl<T: Remotable> Transactable for Inner<T> {
                     return Ok(());
                 }
 
-                match self.remotable.on_transact(code, reader, reply) {
+                if (FIRST_CALL_TRANSACTION..=LAST_CALL_TRANSACTION).contains(&code) {
+                    let interceptors = self.interceptors.read().unwrap().clone();
+                    if let Err(status) = interceptor::intercept(&interceptors, T::descriptor(), code, reader) {
+                        log::debug!("Transaction {} of {} is rejected: {}", code, T::descriptor(), status);
+                        reply.write(&status)?;
+                        return Ok(());
+                    }
+                }
+
+                let result = match self.remotable.on_transact(code, reader, reply) {
                     Ok(_) => Ok(()),
                     Err(err) => {
                         if err == StatusCode::UnknownTransaction {
@@ -191,13 +297,32 @@ impl<T: Remotable> Transactable for Inner<T> {
                             Err(err)
                         }
                     }
-                }
+                };
+
+                self.record_transaction(code, reader, reply, &result);
+                result
             }
         }
     }
 }
 
 
+// Call IResultReceiver::send(resultCode) of the result receiver of SHELL_COMMAND_TRANSACTION.
+fn send_shell_command_result(result_receiver: &SIBinder, code: i32) -> Result<()> {
+    let mut data = Parcel::new();
+    data.write_interface_token(RESULT_RECEIVER_DESCRIPTOR)?;
+    data.write::<i32>(&code)?;
+
+    if let Some(proxy) = result_receiver.as_proxy() {
+        data.set_process(proxy.process()?);
+        proxy.submit_transact(FIRST_CALL_TRANSACTION, &data, FLAG_ONEWAY)?;
+    } else if let Some(transactable) = result_receiver.as_transactable() {
+        transactable.transact(FIRST_CALL_TRANSACTION, &mut data, &mut Parcel::new())?;
+    }
+
+    Ok(())
+}
+
 /// A Binder object that can be used to manage the binder service.
 pub struct Binder<T: 'static + Remotable + Send + Sync> {
     inner: Arc<Inner<T>>,
@@ -215,11 +340,50 @@ impl<T: 'static + Remotable> Binder<T> {
             inner: Arc::new(Inner {
                 remotable,
                 _stability: stability,
+                requesting_sid: AtomicBool::new(false),
+                extension: RwLock::new(None),
+                interceptors: RwLock::new(Vec::new()),
+                recording: Mutex::new(None),
                 strong: Default::default(),
                 weak: Default::default(),
             }),
         }
     }
+
+    /// Request the security context of the caller for the transactions to this binder.
+    /// The context is available from calling_context() while handling a transaction.
+    /// It must be set before the binder is sent to other processes.
+    pub fn set_requesting_sid(&self, requesting_sid: bool) {
+        self.inner.requesting_sid.store(requesting_sid, Ordering::Relaxed);
+    }
+
+    /// Whether the security context of the caller is requested.
+    pub fn is_requesting_sid(&self) -> bool {
+        self.inner.requesting_sid.load(Ordering::Relaxed)
+    }
+
+    /// Add an interceptor of the transactions to this binder.
+    /// It runs after the global interceptors registered by add_transaction_interceptor().
+    pub fn add_interceptor(&self, interceptor: Arc<dyn Interceptor>) {
+        self.inner.interceptors.write().unwrap().push(interceptor);
+    }
+
+    /// Remove an interceptor added by add_interceptor().
+    /// Returns false if the interceptor was not added.
+    pub fn remove_interceptor(&self, interceptor: &Arc<dyn Interceptor>) -> bool {
+        let mut interceptors = self.inner.interceptors.write().unwrap();
+        let len = interceptors.len();
+        interceptors.retain(|i| !Arc::ptr_eq(i, interceptor));
+        len != interceptors.len()
+    }
+
+    /// Attach an extension binder to this binder.
+    /// Clients can get it by IBinder::get_extension() to use an interface which is not
+    /// a part of the interface of this binder. It should be set before this binder is
+    /// published, because clients can't be notified of the change.
+    pub fn set_extension(&self, extension: SIBinder) {
+        *self.inner.extension.write().unwrap() = Some(extension);
+    }
 }
 
 impl<T: 'static + Remotable> Interface for Binder<T> {
@@ -0,0 +1,184 @@
+// Copyright 2022 Jeff Kim <hiking90@gmail.com>
+// SPDX-License-Identifier: Apache-2.0
+
+//! Observation of the binder transactions of the process.
+//!
+//! Observers registered by [`add_transaction_observer`] are notified when an outgoing
+//! transaction is finished by a proxy and when an incoming transaction is handled by
+//! a local binder. With the `tracing` feature, a span is opened for each transaction.
+//! The finished transactions are also counted by the [`crate::stats`] module.
+
+use std::sync::{Arc, RwLock};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+use crate::{
+    binder::{TransactionCode, TransactionFlags},
+    error::Result,
+    stats,
+};
+
+/// The direction of an observed transaction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TransactionDirection {
+    /// A transaction sent to a remote binder.
+    Outgoing,
+    /// A transaction received by a local binder.
+    Incoming,
+}
+
+/// A finished transaction.
+#[derive(Debug, Clone)]
+pub struct TransactionEvent<'a> {
+    pub direction: TransactionDirection,
+    /// The handle of the remote binder. It is None for incoming transactions.
+    pub handle: Option<u32>,
+    pub descriptor: &'a str,
+    pub code: TransactionCode,
+    pub flags: TransactionFlags,
+    pub data_size: usize,
+    pub reply_size: usize,
+    pub duration: Duration,
+    pub result: Result<()>,
+}
+
+/// Observer of the binder transactions of the process.
+///
+/// It is called on the thread which sent or handled the transaction,
+/// so it should return quickly and must not block.
+pub trait TransactionObserver: Send + Sync {
+    fn on_transaction(&self, event: &TransactionEvent);
+}
+
+static OBSERVERS: RwLock<Vec<Arc<dyn TransactionObserver>>> = RwLock::new(Vec::new());
+// To skip the bookkeeping when there is no observer.
+static HAS_OBSERVERS: AtomicBool = AtomicBool::new(false);
+
+/// Register an observer of all transactions of the process.
+pub fn add_transaction_observer(observer: Arc<dyn TransactionObserver>) {
+    let mut observers = OBSERVERS.write().unwrap();
+    observers.push(observer);
+    HAS_OBSERVERS.store(true, Ordering::Release);
+}
+
+/// Unregister an observer registered by add_transaction_observer().
+/// Returns false if the observer was not registered.
+pub fn remove_transaction_observer(observer: &Arc<dyn TransactionObserver>) -> bool {
+    let mut observers = OBSERVERS.write().unwrap();
+    let len = observers.len();
+    observers.retain(|o| !Arc::ptr_eq(o, observer));
+    HAS_OBSERVERS.store(!observers.is_empty(), Ordering::Release);
+    len != observers.len()
+}
+
+// An ongoing transaction which is reported to the observers when it is finished.
+pub(crate) struct Observation<'a> {
+    direction: TransactionDirection,
+    handle: Option<u32>,
+    descriptor: &'a str,
+    code: TransactionCode,
+    flags: TransactionFlags,
+    start: Option<Instant>,
+    #[cfg(feature = "tracing")]
+    span: tracing::span::EnteredSpan,
+}
+
+impl<'a> Observation<'a> {
+    pub(crate) fn outgoing(handle: u32, descriptor: &'a str, code: TransactionCode, flags: TransactionFlags) -> Self {
+        Self::new(TransactionDirection::Outgoing, Some(handle), descriptor, code, flags)
+    }
+
+    pub(crate) fn incoming(descriptor: &'a str, code: TransactionCode, flags: TransactionFlags) -> Self {
+        Self::new(TransactionDirection::Incoming, None, descriptor, code, flags)
+    }
+
+    fn new(direction: TransactionDirection, handle: Option<u32>, descriptor: &'a str,
+        code: TransactionCode, flags: TransactionFlags) -> Self {
+        #[cfg(feature = "tracing")]
+        let span = match direction {
+            TransactionDirection::Outgoing => tracing::debug_span!("binder.outgoing",
+                descriptor, code, flags, handle, result = tracing::field::Empty),
+            TransactionDirection::Incoming => tracing::debug_span!("binder.incoming",
+                descriptor, code, flags, result = tracing::field::Empty),
+        }.entered();
+
+        Observation {
+            direction,
+            handle,
+            descriptor,
+            code,
+            flags,
+            start: (HAS_OBSERVERS.load(Ordering::Acquire) || stats::is_enabled()).then(Instant::now),
+            #[cfg(feature = "tracing")]
+            span,
+        }
+    }
+
+    pub(crate) fn finish(self, data_size: usize, reply_size: usize, result: Result<()>) {
+        #[cfg(feature = "tracing")]
+        match result {
+            Ok(_) => self.span.record("result", "Ok"),
+            Err(err) => self.span.record("result", tracing::field::display(err)),
+        };
+
+        let Some(start) = self.start else {
+            return;
+        };
+
+        let event = TransactionEvent {
+            direction: self.direction,
+            handle: self.handle,
+            descriptor: self.descriptor,
+            code: self.code,
+            flags: self.flags,
+            data_size,
+            reply_size,
+            duration: start.elapsed(),
+            result,
+        };
+
+        if stats::is_enabled() {
+            stats::record(&event);
+        }
+
+        // The lock is released before the observers are called, so they can add or remove observers.
+        let observers = OBSERVERS.read().unwrap().clone();
+        for observer in observers.iter() {
+            observer.on_transaction(&event);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+    use std::sync::atomic::AtomicUsize;
+
+    // An observer which unregisters itself from the callback.
+    struct RemoveSelf {
+        this: Mutex<Option<Arc<dyn TransactionObserver>>>,
+        calls: AtomicUsize,
+    }
+
+    impl TransactionObserver for RemoveSelf {
+        fn on_transaction(&self, _event: &TransactionEvent) {
+            self.calls.fetch_add(1, Ordering::SeqCst);
+            if let Some(this) = self.this.lock().unwrap().take() {
+                assert!(remove_transaction_observer(&this));
+            }
+        }
+    }
+
+    #[test]
+    fn test_remove_observer_in_callback() {
+        let observer = Arc::new(RemoveSelf { this: Mutex::new(None), calls: AtomicUsize::new(0) });
+        let dyn_observer: Arc<dyn TransactionObserver> = observer.clone();
+        *observer.this.lock().unwrap() = Some(dyn_observer.clone());
+        add_transaction_observer(dyn_observer);
+
+        Observation::incoming("test.IRemoveSelf", 1, 0).finish(0, 0, Ok(()));
+        Observation::incoming("test.IRemoveSelf", 1, 0).finish(0, 0, Ok(()));
+        assert_eq!(observer.calls.load(Ordering::SeqCst), 1);
+    }
+}
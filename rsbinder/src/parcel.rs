@@ -19,16 +19,20 @@
 
 use std::vec::Vec;
 use std::default::Default;
+use std::sync::Arc;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd};
 
 use pretty_hex::*;
 
 use crate::{
     error::{Result, StatusCode},
     sys::binder::{binder_size_t, flat_binder_object},
-    sys::{binder_uintptr_t, BINDER_TYPE_FD},
+    sys::{binder_uintptr_t, BINDER_TYPE_FD, BINDER_TYPE_PTR, BINDER_TYPE_FDA, BINDER_BUFFER_FLAG_HAS_PARENT},
+    sys::binder::{binder_buffer_object, binder_fd_array_object, binder_object_header},
     parcelable::*,
     thread_state,
     binder,
+    process_state::ProcessState,
 };
 
 const STRICT_MODE_PENALTY_GATHER: i32 = 1 << 31;
@@ -38,6 +42,12 @@ pub(crate) fn pad_size(len: usize) -> usize {
     (len+3) & (!3)
 }
 
+// The driver aligns each scatter-gather buffer to 8 bytes.
+#[inline]
+fn sg_pad_size(len: usize) -> usize {
+    (len+7) & (!7)
+}
+
 pub(crate) trait CharType : Clone {
     type Output;
     fn as_i32(&self) -> i32;
@@ -167,6 +177,11 @@ pub struct Parcel {
     request_header_present: bool,
     work_source_request_header_pos: usize,
     free_buffer: Option<FnFreeBuffer>,
+    process: Option<Arc<ProcessState>>,
+    // Scatter-gather buffers referred by BINDER_TYPE_PTR objects of an outgoing parcel.
+    sg_buffers: Vec<Box<[u8]>>,
+    // File descriptors referred by BINDER_TYPE_FDA objects of an outgoing parcel.
+    owned_fds: Vec<OwnedFd>,
 }
 
 impl Default for Parcel {
@@ -189,12 +204,18 @@ impl Parcel {
             request_header_present: false,
             work_source_request_header_pos: 0,
             free_buffer: None,
+            process: None,
+            sg_buffers: Vec::new(),
+            owned_fds: Vec::new(),
         }
     }
 
     pub fn from_ipc_parts(data: *mut u8, length: usize,
             objects: *mut binder_size_t, object_count: usize,
             free_buffer: fn(Option<&Parcel>, binder_uintptr_t, usize, binder_uintptr_t, usize) -> Result<()>) -> Self {
+        // The buffer must stay mapped until it is freed.
+        let process = thread_state::current_process();
+        process.acquire_buffer();
         Parcel {
             data: ParcelData::from_raw_parts_mut(data, length),
             objects: ParcelData::from_raw_parts_mut(objects, object_count),
@@ -203,6 +224,9 @@ impl Parcel {
             request_header_present: false,
             work_source_request_header_pos: 0,
             free_buffer: Some(free_buffer),
+            process: Some(process),
+            sg_buffers: Vec::new(),
+            owned_fds: Vec::new(),
         }
     }
 
@@ -217,9 +241,22 @@ impl Parcel {
             request_header_present: false,
             work_source_request_header_pos: 0,
             free_buffer: None,
+            process: None,
+            sg_buffers: Vec::new(),
+            owned_fds: Vec::new(),
         }
     }
 
+    /// Bind the parcel to the ProcessState whose binder driver will carry it.
+    pub(crate) fn set_process(&mut self, process: Arc<ProcessState>) {
+        self.process = Some(process);
+    }
+
+    /// Get the ProcessState which the binder objects of this parcel belong to.
+    pub(crate) fn process(&self) -> Arc<ProcessState> {
+        self.process.clone().unwrap_or_else(thread_state::current_process)
+    }
+
 
     pub fn as_mut_ptr(&mut self) -> *mut u8 {
         self.data.as_mut_ptr()
@@ -229,6 +266,11 @@ impl Parcel {
         self.data.as_ptr()
     }
 
+    /// Get the raw data of the parcel.
+    pub fn as_slice(&self) -> &[u8] {
+        self.data.as_slice()
+    }
+
     pub fn capacity(&self) -> usize {
         self.data.capacity()
     }
@@ -311,9 +353,15 @@ impl Parcel {
             return Ok(obj);
         }
 
-        let objects = self.objects.as_slice();
+        Self::find_object(&self.objects, &mut self.next_object_hint, data_pos)?;
+        Ok(obj)
+    }
+
+    // Find the index of the object at data_pos from the offsets of the objects.
+    fn find_object(objects: &ParcelData<binder_size_t>, next_object_hint: &mut usize, data_pos: u64) -> Result<usize> {
+        let objects = objects.as_slice();
         let count = objects.len();
-        let mut opos = self.next_object_hint;
+        let mut opos = *next_object_hint;
 
         if count > 0 {
             log::trace!("Parcel looking for obj at {}, hint={}", data_pos, opos);
@@ -325,8 +373,8 @@ impl Parcel {
                 opos = count - 1;
             }
             if objects[opos] == data_pos {
-                self.next_object_hint = opos + 1;
-                return Ok(obj);
+                *next_object_hint = opos + 1;
+                return Ok(opos);
             }
 
             while opos > 0 && objects[opos] > data_pos {
@@ -334,8 +382,8 @@ impl Parcel {
             }
 
             if objects[opos] == data_pos {
-                self.next_object_hint = opos + 1;
-                return Ok(obj);
+                *next_object_hint = opos + 1;
+                return Ok(opos);
             }
         }
         log::error!("Parcel: unable to find object at index {}", data_pos);
@@ -591,13 +639,155 @@ impl Parcel {
         self.write_aligned(obj);
 
         if null_meta || obj.pointer() != 0 {
-            obj.acquire()?;
+            obj.acquire(|| self.process())?;
             self.objects.push(data_pos as _);
         }
 
         Ok(())
     }
 
+    /// Write a scatter-gather buffer object(BINDER_TYPE_PTR) carrying `buffer` out of line.
+    /// The driver copies the buffer into the transaction buffer of the receiver.
+    ///
+    /// If `parent` is given as (object index of the parent buffer, offset in the parent buffer),
+    /// the driver patches the pointer at the offset of the parent buffer to point to
+    /// the received copy of `buffer`. Parents must be written before their children.
+    /// It returns the object index of the buffer which is used as a parent.
+    pub fn write_buffer(&mut self, buffer: &[u8], parent: Option<(usize, usize)>) -> Result<usize> {
+        let (flags, parent, parent_offset) = match parent {
+            Some((parent, parent_offset)) => {
+                let length = self.buffer_object_length(parent)?;
+                if parent_offset + std::mem::size_of::<binder_uintptr_t>() > length {
+                    log::error!("Parcel::write_buffer: parent offset {} is out of the parent buffer({}).", parent_offset, length);
+                    return Err(StatusCode::BadValue);
+                }
+                (BINDER_BUFFER_FLAG_HAS_PARENT, parent, parent_offset)
+            }
+            None => (0, 0, 0),
+        };
+
+        let buffer: Box<[u8]> = buffer.into();
+        let obj = binder_buffer_object {
+            hdr: binder_object_header {
+                type_: BINDER_TYPE_PTR,
+            },
+            flags,
+            buffer: buffer.as_ptr() as _,
+            length: buffer.len() as _,
+            parent: parent as _,
+            parent_offset: parent_offset as _,
+        };
+        self.sg_buffers.push(buffer);
+
+        Ok(self.write_sg_object(&obj))
+    }
+
+    /// Write an array of file descriptors(BINDER_TYPE_FDA).
+    /// The file descriptors are duplicated and stored in a scatter-gather buffer,
+    /// and the driver installs them in the receiver.
+    pub fn write_fd_array(&mut self, fds: &[RawFd]) -> Result<()> {
+        let mut owned_fds = Vec::with_capacity(fds.len());
+        for fd in fds {
+            let fd = nix::fcntl::fcntl(*fd, nix::fcntl::FcntlArg::F_DUPFD_CLOEXEC(0))?;
+            owned_fds.push(unsafe { OwnedFd::from_raw_fd(fd) });
+        }
+
+        let buffer: Vec<u8> = owned_fds.iter()
+            .flat_map(|fd| (fd.as_raw_fd() as u32).to_ne_bytes())
+            .collect();
+        let parent = self.write_buffer(&buffer, None)?;
+        self.owned_fds.append(&mut owned_fds);
+
+        let obj = binder_fd_array_object {
+            hdr: binder_object_header {
+                type_: BINDER_TYPE_FDA,
+            },
+            pad: 0,
+            num_fds: fds.len() as _,
+            parent: parent as _,
+            parent_offset: 0,
+        };
+        self.write_sg_object(&obj);
+
+        Ok(())
+    }
+
+    /// Read a scatter-gather buffer object written by write_buffer().
+    pub fn read_buffer(&mut self) -> Result<&[u8]> {
+        let obj = self.read_sg_object::<binder_buffer_object>(BINDER_TYPE_PTR)?.0;
+        if obj.length == 0 {
+            return Ok(&[]);
+        }
+        // The buffer lives in the transaction buffer of the driver or in sg_buffers
+        // as long as the parcel.
+        Ok(unsafe { std::slice::from_raw_parts(obj.buffer as *const u8, obj.length as _) })
+    }
+
+    /// Read an array of file descriptors written by write_fd_array().
+    /// The returned file descriptors are duplicated and owned by the caller.
+    pub fn read_fd_array(&mut self) -> Result<Vec<OwnedFd>> {
+        let (buffer, buffer_index) = self.read_sg_object::<binder_buffer_object>(BINDER_TYPE_PTR)?;
+        let obj = self.read_sg_object::<binder_fd_array_object>(BINDER_TYPE_FDA)?.0;
+
+        let fds_size = (obj.num_fds as usize) * std::mem::size_of::<u32>();
+        if obj.parent as usize != buffer_index || (obj.parent_offset as usize) + fds_size > buffer.length as usize {
+            log::error!("Parcel::read_fd_array: the fd array doesn't match its parent buffer.");
+            return Err(StatusCode::BadValue);
+        }
+
+        let fds = unsafe {
+            std::slice::from_raw_parts((buffer.buffer + obj.parent_offset) as *const u32, obj.num_fds as _)
+        };
+        // The driver closes the received file descriptors when the transaction buffer is freed.
+        fds.iter().map(|fd| {
+            let fd = nix::fcntl::fcntl(*fd as _, nix::fcntl::FcntlArg::F_DUPFD_CLOEXEC(0))?;
+            Ok(unsafe { OwnedFd::from_raw_fd(fd) })
+        }).collect()
+    }
+
+    /// Get the total size of the scatter-gather buffers to be sent with the parcel.
+    pub(crate) fn sg_buffers_size(&self) -> usize {
+        self.sg_buffers.iter().map(|buffer| sg_pad_size(buffer.len())).sum()
+    }
+
+    fn write_sg_object<T>(&mut self, obj: &T) -> usize {
+        let data_pos = self.pos;
+        self.write_aligned(obj);
+        self.objects.push(data_pos as _);
+        self.objects.len() - 1
+    }
+
+    fn read_sg_object<T: Copy>(&mut self, type_: u32) -> Result<(T, usize)> {
+        let data_pos = self.pos as u64;
+        let obj: T = unsafe {
+            std::ptr::read_unaligned(self.read_aligned_data(std::mem::size_of::<T>())?.as_ptr() as *const T)
+        };
+        let index = Self::find_object(&self.objects, &mut self.next_object_hint, data_pos)?;
+
+        let hdr: &binder_object_header = unsafe { &*(&obj as *const T as *const binder_object_header) };
+        if hdr.type_ != type_ {
+            log::error!("Parcel: expected object type {:08x} but found {:08x}", type_, hdr.type_);
+            return Err(StatusCode::BadType);
+        }
+        Ok((obj, index))
+    }
+
+    // Get the length of the buffer object at the object index.
+    fn buffer_object_length(&self, index: usize) -> Result<usize> {
+        let pos = *self.objects.as_slice().get(index).ok_or_else(|| {
+            log::error!("Parcel: no object at index {}", index);
+            StatusCode::BadIndex
+        })? as usize;
+        let obj: binder_buffer_object = unsafe {
+            std::ptr::read_unaligned(self.data.as_ptr().add(pos) as *const binder_buffer_object)
+        };
+        if obj.hdr.type_ != BINDER_TYPE_PTR {
+            log::error!("Parcel: the object at index {} is not a buffer.", index);
+            return Err(StatusCode::BadType);
+        }
+        Ok(obj.length as _)
+    }
+
     pub(crate) fn write_interface_token(&mut self, interface: &str) -> Result<()> {
         self.write(&(thread_state::strict_mode_policy() | STRICT_MODE_PENALTY_GATHER))?;
         self.update_work_source_request_header_pos();
@@ -697,6 +887,17 @@ impl Parcel {
 
         let num_objects = last_idx - first_idx + 1;
 
+        // The parent indices of buffers and fd arrays can't be relocated.
+        for off in other.objects.as_slice() {
+            if *off >= offset as _ && *off < (offset + size) as _ {
+                let obj: &flat_binder_object = (other.data.as_ptr(), *off as usize).into();
+                if obj.header_type() == BINDER_TYPE_PTR || obj.header_type() == BINDER_TYPE_FDA {
+                    log::error!("Parcel::append_from: scatter-gather objects can't be appended.");
+                    return Err(StatusCode::InvalidOperation)
+                }
+            }
+        }
+
         self.data.reserve(self.pos + size);
         unsafe {
             std::ptr::copy_nonoverlapping::<u8>(
@@ -710,6 +911,7 @@ impl Parcel {
         self.set_data_position(self.pos + size);
 
         if num_objects > 0 {
+            let process = self.process.clone();
             let mut idx = self.objects.len();
             self.objects.resize(idx + (num_objects as usize));
 
@@ -719,7 +921,7 @@ impl Parcel {
                 objects[idx] = off as _;
                 idx += 1;
                 let flat: &mut flat_binder_object = (self.data.as_mut_ptr(), off).into();
-                flat.acquire()?;
+                flat.acquire(|| process.clone().unwrap_or_else(thread_state::current_process))?;
                 if flat.header_type() == BINDER_TYPE_FD {
                     flat.set_handle(nix::fcntl::fcntl(flat.handle() as _, nix::fcntl::FcntlArg::F_DUPFD_CLOEXEC(0))? as _);
                     flat.set_cookie(1);
@@ -737,7 +939,7 @@ impl Parcel {
 
         for pos in self.objects.as_slice() {
             let obj: &flat_binder_object = (self.data.as_ptr(), *pos as usize).into();
-            obj.release().map_err(|e| log::error!("Parcel: unable to release object: {:?}", e)).ok();
+            obj.release(|| self.process()).map_err(|e| log::error!("Parcel: unable to release object: {:?}", e)).ok();
         }
     }
 }
@@ -746,11 +948,15 @@ impl Drop for Parcel {
     fn drop(&mut self) {
         match self.free_buffer {
             Some(free_buffer) => {
-                free_buffer(Some(self),
-                    self.data.as_ptr() as _,
-                    self.data.len(),
-                    self.objects.as_ptr() as _,
-                    self.objects.len()).unwrap();
+                let process = self.process();
+                thread_state::enter(&process, || {
+                    free_buffer(Some(self),
+                        self.data.as_ptr() as _,
+                        self.data.len(),
+                        self.objects.as_ptr() as _,
+                        self.objects.len())
+                }).unwrap();
+                process.release_buffer();
             }
             None => {
                 self.release_objects();
@@ -911,6 +1117,30 @@ mod tests {
     fn test_errors() -> Result<()> {
         Ok(())
     }
+
+    #[test]
+    fn test_sg_objects() -> Result<()> {
+        use std::os::fd::AsRawFd;
+
+        let mut parcel = Parcel::new();
+
+        let parent = parcel.write_buffer(&[0u8; 16], None)?;
+        parcel.write_buffer(b"child", Some((parent, 8)))?;
+        assert_eq!(parcel.write_buffer(b"child", Some((parent, 12))), Err(StatusCode::BadValue));
+
+        let file = std::fs::File::open("/dev/null").unwrap();
+        parcel.write_fd_array(&[file.as_raw_fd(), file.as_raw_fd()])?;
+        assert_eq!(parcel.sg_buffers_size(), 16 + 8 + 8);
+
+        parcel.set_data_position(0);
+        assert_eq!(parcel.read_buffer()?, &[0u8; 16]);
+        assert_eq!(parcel.read_buffer()?, b"child");
+        let fds = parcel.read_fd_array()?;
+        assert_eq!(fds.len(), 2);
+        assert_ne!(fds[0].as_raw_fd(), file.as_raw_fd());
+
+        Ok(())
+    }
 }
 
 
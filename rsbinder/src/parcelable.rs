@@ -20,7 +20,6 @@
 use crate::{
     sys::*,
     error::*,
-    process_state::*,
     binder::*,
     parcel::Parcel,
     binder_object::*,
@@ -508,7 +507,7 @@ impl DeserializeOption for SIBinder {
             }
 
             BINDER_TYPE_HANDLE => {
-                let res = ProcessState::as_self()
+                let res = parcel.process()
                     .strong_proxy_for_handle_stability(flat.handle(), stability.try_into()?)?;
                 Ok(Some(res))
             }
@@ -5,11 +5,12 @@ use std::os::raw::c_void;
 use std::ptr::NonNull;
 use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use std::collections::HashMap;
-use std::sync::{Arc, RwLock, OnceLock};
+use std::sync::{Arc, Mutex, RwLock, OnceLock, Weak};
 use std::path::{Path, PathBuf};
 use std::fs::File;
-use std::os::unix::io::AsRawFd;
+use std::os::unix::io::{AsRawFd, RawFd};
 use std::thread;
+use std::time::{Duration, Instant};
 
 use crate::{
     error::*,
@@ -17,8 +18,63 @@ use crate::{
     sys::binder,
     proxy::*,
     thread_state,
+    binder_object::local_node_pointer,
 };
 
+/// Reference counts the driver holds on the node referred to by a remote handle.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct NodeRefInfo {
+    pub handle: u32,
+    pub strong_count: u32,
+    pub weak_count: u32,
+}
+
+/// Kernel side state of a local binder node.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct NodeDebugInfo {
+    pub ptr: u64,
+    pub cookie: u64,
+    pub has_strong_ref: bool,
+    pub has_weak_ref: bool,
+}
+
+impl NodeDebugInfo {
+    /// Check whether this node is the local binder `binder`.
+    pub fn is_node_of(&self, binder: &SIBinder) -> bool {
+        !binder.is_remote() && local_node_pointer(binder) == (self.ptr as _, self.cookie as _)
+    }
+}
+
+/// Iterator over the local binder nodes of a ProcessState, in the order of their pointers.
+/// It is created by [`ProcessState::nodes()`].
+pub struct Nodes<'a> {
+    process: &'a ProcessState,
+    ptr: binder::binder_uintptr_t,
+    done: bool,
+}
+
+impl Iterator for Nodes<'_> {
+    type Item = NodeDebugInfo;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+
+        match self.process.node_debug_info_after(self.ptr) {
+            Ok(Some(info)) => {
+                self.ptr = info.ptr as _;
+                Some(info)
+            }
+            // Errors are logged by node_debug_info_after().
+            Ok(None) | Err(_) => {
+                self.done = true;
+                None
+            }
+        }
+    }
+}
+
 #[derive(Debug, Clone, Copy)]
 pub enum CallRestriction {
     // all calls okay
@@ -30,7 +86,28 @@ pub enum CallRestriction {
 }
 
 const DEFAULT_MAX_BINDER_THREADS: u32 = 15;
-const DEFAULT_ENABLE_ONEWAY_SPAM_DETECTION: u32 = 1;
+const DEFAULT_ENABLE_ONEWAY_SPAM_DETECTION: bool = true;
+
+// How often ProcessState::shutdown() wakes up the binder threads while waiting for them to leave.
+const SHUTDOWN_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+type InitResult<T> = std::result::Result<T, Box<dyn std::error::Error>>;
+
+// Bits of binder_frozen_status_info::sync_recv.
+const FROZEN_SYNC_RECEIVED: u32 = 1 << 0;
+const FROZEN_SYNC_PENDING: u32 = 1 << 1;
+
+/// Transactions of a process seen by the binder driver while the process was frozen.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct FrozenInfo {
+    /// The process received synchronous transactions while it was frozen.
+    pub sync_received: bool,
+    /// The process received oneway transactions while it was frozen.
+    pub async_received: bool,
+    /// The process still has synchronous transactions in flight.
+    /// Old kernels don't report it and it is always false.
+    pub sync_pending: bool,
+}
 
 struct MemoryMap {
     ptr: NonNull<c_void>,
@@ -39,11 +116,169 @@ struct MemoryMap {
 unsafe impl Sync for MemoryMap {}
 unsafe impl Send for MemoryMap {}
 
+#[derive(Default)]
+struct BufferUsersState {
+    count: usize,
+    unmap_requested: bool,
+}
+
+// The users of the buffers of the memory map: the Parcels referring to a buffer and the
+// threads waiting for a reply. The memory map is unmapped when it is requested and all
+// users are gone.
+#[derive(Default)]
+struct BufferUsers(Mutex<BufferUsersState>);
+
+impl BufferUsers {
+    // Add a user which is going to receive a buffer. It fails once the unmap is requested.
+    fn try_acquire(&self) -> bool {
+        let mut state = self.0.lock().unwrap();
+        if state.unmap_requested {
+            return false;
+        }
+        state.count += 1;
+        true
+    }
+
+    // Add a user of a buffer which is already received.
+    fn acquire(&self) {
+        self.0.lock().unwrap().count += 1;
+    }
+
+    // Returns true if the caller is the last user after the unmap is requested.
+    fn release(&self) -> bool {
+        let mut state = self.0.lock().unwrap_or_else(|e| e.into_inner());
+        state.count -= 1;
+        state.count == 0 && state.unmap_requested
+    }
+
+    // Returns true if the memory map can be unmapped now. Otherwise, the last user unmaps it.
+    fn request_unmap(&self) -> bool {
+        let mut state = self.0.lock().unwrap();
+        state.unmap_requested = true;
+        state.count == 0
+    }
+}
+
+/// Builder for [`ProcessState`].
+///
+/// Unlike [`ProcessState::init()`], the builder reports initialization failures
+/// to the caller instead of panicking, so a process can keep running without binder.
+/// [`init()`](Self::init) sets up the process-wide default instance and
+/// [`build()`](Self::build) creates an additional instance for another binder device.
+///
+/// ```no_run
+/// # use rsbinder::ProcessState;
+/// match ProcessState::builder().max_threads(4).init() {
+///     Ok(process) => { /* binder is available */ }
+///     Err(e) => log::warn!("Binder is not available: {}", e),
+/// }
+/// ```
+#[derive(Debug, Clone)]
+pub struct ProcessStateBuilder {
+    driver_name: PathBuf,
+    max_threads: u32,
+    mmap_size: Option<usize>,
+    thread_name_prefix: Option<String>,
+    oneway_spam_detection: bool,
+    call_restriction: CallRestriction,
+}
+
+impl Default for ProcessStateBuilder {
+    fn default() -> Self {
+        Self {
+            driver_name: PathBuf::from(crate::DEFAULT_BINDER_PATH),
+            max_threads: DEFAULT_MAX_BINDER_THREADS,
+            mmap_size: None,
+            thread_name_prefix: None,
+            oneway_spam_detection: DEFAULT_ENABLE_ONEWAY_SPAM_DETECTION,
+            call_restriction: CallRestriction::None,
+        }
+    }
+}
+
+impl ProcessStateBuilder {
+    /// Set the binder device path. The default is DEFAULT_BINDER_PATH.
+    pub fn driver<P: AsRef<Path>>(mut self, driver_name: P) -> Self {
+        self.driver_name = driver_name.as_ref().to_path_buf();
+        self
+    }
+
+    /// Set the maximum number of binder threads the kernel may ask this process to spawn.
+    /// The value is passed to the driver as is.
+    pub fn max_threads(mut self, max_threads: u32) -> Self {
+        self.max_threads = max_threads;
+        self
+    }
+
+    /// Set the size of the memory map used to receive transactions.
+    /// The default is 1MB minus two pages, the same as Android.
+    pub fn mmap_size(mut self, mmap_size: usize) -> Self {
+        self.mmap_size = Some(mmap_size);
+        self
+    }
+
+    /// Set the prefix of the binder thread names.
+    /// The default is the file name of the binder device.
+    pub fn thread_name_prefix(mut self, prefix: &str) -> Self {
+        self.thread_name_prefix = Some(prefix.to_owned());
+        self
+    }
+
+    /// Enable or disable the oneway spam detection of the driver. It is enabled by default.
+    pub fn oneway_spam_detection(mut self, enable: bool) -> Self {
+        self.oneway_spam_detection = enable;
+        self
+    }
+
+    /// Set the initial call restriction of the process.
+    pub fn call_restriction(mut self, call_restriction: CallRestriction) -> Self {
+        self.call_restriction = call_restriction;
+        self
+    }
+
+    /// Initialize the process-wide default ProcessState.
+    /// It fails if ProcessState is already initialized, because the builder settings
+    /// can't be applied to the existing instance.
+    pub fn init(self) -> InitResult<&'static ProcessState> {
+        self.init_default_instance(false)
+    }
+
+    // Initialize the default instance, or return the existing one if `reuse` is set.
+    fn init_default_instance(self, reuse: bool) -> InitResult<&'static ProcessState> {
+        static INIT_LOCK: Mutex<()> = Mutex::new(());
+
+        // OnceLock::get_or_try_init() is not stable yet, so serialize the initialization.
+        let _guard = INIT_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(instance) = ProcessState::instance().get() {
+            if reuse {
+                return Ok(instance);
+            }
+            return Err(format!("ProcessState is already initialized with '{}'",
+                instance.driver_name.display()).into());
+        }
+
+        let instance = self.build()?;
+        Ok(ProcessState::instance().get_or_init(|| instance).as_ref())
+    }
+
+    /// Create a new ProcessState that is independent of the process-wide default instance.
+    ///
+    /// Use it to talk to a second binder device in the same process.
+    /// Proxies, thread pools and service manager clients obtained from the returned
+    /// instance are bound to its binder device.
+    pub fn build(self) -> InitResult<Arc<ProcessState>> {
+        ProcessState::inner_init(self)
+    }
+}
+
 pub struct ProcessState {
+    this: Weak<ProcessState>,
     max_threads: u32,
     driver_name: PathBuf,
+    thread_name_prefix: Option<String>,
     driver: Arc<File>,
     mmap: RwLock<MemoryMap>,
+    buffer_users: BufferUsers,
     context_manager: RwLock<Option<SIBinder>>,
     handle_to_proxy: RwLock<HashMap<u32, WIBinder>>,
     disable_background_scheduling: AtomicBool,
@@ -52,21 +287,60 @@ pub struct ProcessState {
     thread_pool_seq: AtomicUsize,
     kernel_started_threads: AtomicUsize,
     pub(crate) current_threads: AtomicUsize,
+    // The callbacks to stop the drivers polling for commands out of the thread pool.
+    pollers: Mutex<Vec<StopPolling>>,
+    shutting_down: AtomicBool,
+    shut_down: AtomicBool,
+}
+
+type StopPolling = Arc<dyn Fn() + Send + Sync>;
+
+/// The registration of an event loop polling for the commands of a ProcessState out of the
+/// thread pool, like `TokioPollingThread`. It is unregistered when dropped.
+/// See [`ProcessState::add_poller()`].
+pub struct Poller {
+    process: Arc<ProcessState>,
+    stop: StopPolling,
+}
+
+impl Drop for Poller {
+    fn drop(&mut self) {
+        self.process.pollers.lock().unwrap().retain(|stop| !Arc::ptr_eq(stop, &self.stop));
+    }
 }
 
 impl ProcessState {
-    fn instance() -> &'static OnceLock<ProcessState> {
-        static INSTANCE: OnceLock<ProcessState> = OnceLock::new();
+    fn instance() -> &'static OnceLock<Arc<ProcessState>> {
+        static INSTANCE: OnceLock<Arc<ProcessState>> = OnceLock::new();
         &INSTANCE
     }
 
-    /// Get ProcessState instance.
+    /// Get the process-wide default ProcessState instance.
     /// If ProcessState is not initialized, it will panic.
-    /// If you want to initialize ProcessState, use init() or init_default().
+    /// If you want to initialize ProcessState, use init(), init_default() or builder().
     pub fn as_self() -> &'static ProcessState {
         Self::instance().get().expect("ProcessState is not initialized!")
     }
 
+    pub(crate) fn try_default_instance() -> Option<Arc<ProcessState>> {
+        Self::instance().get().cloned()
+    }
+
+    /// Get a shared reference to this ProcessState.
+    pub fn as_arc(&self) -> Arc<ProcessState> {
+        self.this.upgrade().expect("ProcessState is already dropped.")
+    }
+
+    /// Get the path of the binder device of this ProcessState.
+    pub fn driver_name(&self) -> &Path {
+        &self.driver_name
+    }
+
+    /// Create a builder to initialize ProcessState with custom settings.
+    pub fn builder() -> ProcessStateBuilder {
+        ProcessStateBuilder::default()
+    }
+
     pub fn set_call_restriction(&self, call_restriction: CallRestriction) {
         let mut self_call_restriction = self.call_restriction.write().unwrap();
         *self_call_restriction = call_restriction;
@@ -76,18 +350,13 @@ impl ProcessState {
         *self.call_restriction.read().unwrap()
     }
 
-    fn inner_init(driver_name: &str, max_threads: u32) -> std::result::Result<ProcessState, Box<dyn std::error::Error>> {
-        let max_threads = if max_threads != 0 && max_threads < DEFAULT_MAX_BINDER_THREADS {
-            max_threads
-        } else {
-            DEFAULT_MAX_BINDER_THREADS
-        };
-
-        let driver_name = PathBuf::from(driver_name);
-
-        let driver = open_driver(&driver_name, max_threads)?;
+    fn inner_init(builder: ProcessStateBuilder) -> InitResult<Arc<ProcessState>> {
+        let driver = open_driver(&builder.driver_name, builder.max_threads, builder.oneway_spam_detection)?;
 
-        let vm_size = ((1024 * 1024) - nix::unistd::sysconf(nix::unistd::SysconfVar::PAGE_SIZE)?.unwrap() * 2) as usize;
+        let vm_size = match builder.mmap_size {
+            Some(size) => size,
+            None => ((1024 * 1024) - nix::unistd::sysconf(nix::unistd::SysconfVar::PAGE_SIZE)?.unwrap_or(4096) * 2) as usize,
+        };
         let vm_size = std::num::NonZeroUsize::new(vm_size).ok_or("vm_size is zero!")?;
 
         let mmap = unsafe {
@@ -96,41 +365,53 @@ impl ProcessState {
                 nix::sys::mman::ProtFlags::PROT_READ,
                 nix::sys::mman::MapFlags::MAP_PRIVATE | nix::sys::mman::MapFlags::MAP_NORESERVE,
                 &driver,
-                0)?;
+                0)
+                .map_err(|e| format!("Mapping {} bytes of '{}' failed: {}", vm_size, builder.driver_name.display(), e))?;
 
             (vm_start, vm_size)
         };
 
-        Ok(ProcessState {
-            max_threads,
-            driver_name,
+        Ok(Arc::new_cyclic(|this| ProcessState {
+            this: this.clone(),
+            max_threads: builder.max_threads,
+            driver_name: builder.driver_name,
+            thread_name_prefix: builder.thread_name_prefix,
             driver: driver.into(),
             mmap: RwLock::new(MemoryMap { ptr: mmap.0, size: mmap.1.get() }),
+            buffer_users: BufferUsers::default(),
             context_manager: RwLock::new(None),
             handle_to_proxy: RwLock::new(HashMap::new()),
             disable_background_scheduling: AtomicBool::new(false),
-            call_restriction: RwLock::new(CallRestriction::None),
+            call_restriction: RwLock::new(builder.call_restriction),
             thread_pool_started: AtomicBool::new(false),
             thread_pool_seq: AtomicUsize::new(1),
             kernel_started_threads: AtomicUsize::new(0),
             current_threads: AtomicUsize::new(0),
-        })
+            pollers: Mutex::new(Vec::new()),
+            shutting_down: AtomicBool::new(false),
+            shut_down: AtomicBool::new(false),
+        }))
     }
 
     /// Initialize ProcessState with binder path and max threads.
     /// The meaning of zero max threads is to use the default value. It is dependent on the kernel.
     /// If you want to use the default binder path, use init_default().
+    /// It panics if the initialization fails. Use builder() to handle the error.
+    /// If ProcessState is already initialized, the existing instance is returned.
     pub fn init(driver_name: &str, max_threads: u32) -> &'static ProcessState {
-        // TODO: panic! is not good. It should return Result.
-        // But, get_or_try_init is not stable yet.
-        Self::instance().get_or_init(|| {
-            match Self::inner_init(driver_name, max_threads) {
-                Ok(instance) => instance,
-                Err(e) => {
-                    panic!("Error in init(): {}", e);
-                }
+        let max_threads = if max_threads != 0 {
+            max_threads
+        } else {
+            DEFAULT_MAX_BINDER_THREADS
+        };
+
+        // The existing instance is returned as before the builder was introduced.
+        match Self::builder().driver(driver_name).max_threads(max_threads).init_default_instance(true) {
+            Ok(instance) => instance,
+            Err(e) => {
+                panic!("Error in init(): {}", e);
             }
-        })
+        }
     }
 
     /// Initialize ProcessState with default binder path and max threads.
@@ -140,7 +421,9 @@ impl ProcessState {
         Self::init(crate::DEFAULT_BINDER_PATH, 0)
     }
 
-    /// Get binder service manager.
+    /// Make `binder` the context manager(service manager) of the binder device.
+    /// If `binder` requests the security context of callers with Binder::set_requesting_sid(),
+    /// the driver delivers it for the transactions to the context manager too.
     pub fn become_context_manager(&self, binder: SIBinder) -> std::result::Result<(), Box<dyn std::error::Error>> {
         let mut context_manager = self.context_manager.write().unwrap();
 
@@ -148,6 +431,9 @@ impl ProcessState {
             let obj = std::mem::MaybeUninit::<binder::flat_binder_object>::zeroed();
             let mut obj = unsafe { obj.assume_init() };
             obj.flags = binder::FLAT_BINDER_FLAG_ACCEPTS_FDS;
+            if binder.is_requesting_sid() {
+                obj.flags |= binder::FLAT_BINDER_FLAG_TXN_SECURITY_CTX;
+            }
 
             unsafe {
                 let driver_fd = self.driver.as_raw_fd();
@@ -181,6 +467,10 @@ impl ProcessState {
     }
 
     pub(crate) fn strong_proxy_for_handle_stability(&self, handle: u32, stability: Stability) -> Result<SIBinder> {
+        if self.shut_down.load(Ordering::Acquire) {
+            return Err(StatusCode::DeadObject);
+        }
+
         // Double-Checked Locking Pattern is used.
         if let Some(weak) = self.handle_to_proxy.read().unwrap().get(&handle) {
             return weak.upgrade()
@@ -191,18 +481,21 @@ impl ProcessState {
             return weak.upgrade()
         }
 
-        if handle == 0 {
-            let original_call_restriction = thread_state::call_restriction();
-            thread_state::set_call_restriction(CallRestriction::None);
+        let this = self.as_arc();
+        let interface = thread_state::enter(&this, || -> Result<String> {
+            if handle == 0 {
+                let original_call_restriction = thread_state::call_restriction();
+                thread_state::set_call_restriction(CallRestriction::None);
 
-            thread_state::ping_binder(handle)?;
+                thread_state::ping_binder(handle)?;
 
-            thread_state::set_call_restriction(original_call_restriction);
-        }
+                thread_state::set_call_restriction(original_call_restriction);
+            }
 
-        let interface: String = thread_state::query_interface(handle)?;
+            thread_state::query_interface(handle)
+        })?;
 
-        let proxy: Arc<dyn IBinder> = ProxyHandle::new(handle, &interface, stability);
+        let proxy: Arc<dyn IBinder> = ProxyHandle::new(handle, &interface, stability, &this);
         let weak = WIBinder::new(proxy)?;
 
         handle_to_proxy.insert(handle, weak.clone());
@@ -231,32 +524,182 @@ impl ProcessState {
         self.driver.clone()
     }
 
+    /// Get the reference counts of the node referred to by the remote binder `binder`.
+    ///
+    /// The driver allows it only for the context manager.
+    /// [`StatusCode::BadType`] is returned for a local binder; use [`node_debug_info()`](Self::node_debug_info) instead.
+    pub fn node_info_for_ref(&self, binder: &SIBinder) -> Result<NodeRefInfo> {
+        let handle = match binder.as_proxy() {
+            Some(proxy) => proxy.handle(),
+            None => return Err(StatusCode::BadType),
+        };
+
+        let mut info = binder::binder_node_info_for_ref {
+            handle,
+            strong_count: 0,
+            weak_count: 0,
+            reserved1: 0,
+            reserved2: 0,
+            reserved3: 0,
+        };
+
+        unsafe {
+            binder::get_node_info_for_ref(self.driver.as_raw_fd(), &mut info)
+                .map_err(|e| {
+                    log::error!("Binder ioctl to get node info for handle {} failed: {}", handle, e);
+                    e
+                })?;
+        }
+
+        Ok(NodeRefInfo {
+            handle,
+            strong_count: info.strong_count,
+            weak_count: info.weak_count,
+        })
+    }
+
+    /// Get the kernel side state of the local binder `binder`.
+    /// None is returned if the binder has never been sent to other processes.
+    pub fn node_debug_info(&self, binder: &SIBinder) -> Result<Option<NodeDebugInfo>> {
+        if binder.is_remote() {
+            return Err(StatusCode::BadType);
+        }
+
+        let (ptr, _) = local_node_pointer(binder);
+        // The driver returns the first node whose pointer is greater than the given one.
+        let info = self.node_debug_info_after(ptr - 1)?;
+        Ok(info.filter(|info| info.is_node_of(binder)))
+    }
+
+    /// Iterate over all local binder nodes known to the driver.
+    pub fn nodes(&self) -> Nodes<'_> {
+        Nodes {
+            process: self,
+            ptr: 0,
+            done: false,
+        }
+    }
+
+    fn node_debug_info_after(&self, ptr: binder::binder_uintptr_t) -> Result<Option<NodeDebugInfo>> {
+        let mut info = binder::binder_node_debug_info {
+            ptr,
+            cookie: 0,
+            has_strong_ref: 0,
+            has_weak_ref: 0,
+        };
+
+        unsafe {
+            binder::get_node_debug_info(self.driver.as_raw_fd(), &mut info)
+                .map_err(|e| {
+                    log::error!("Binder ioctl to get node debug info failed: {}", e);
+                    e
+                })?;
+        }
+
+        if info.ptr == 0 {
+            Ok(None)
+        } else {
+            Ok(Some(NodeDebugInfo {
+                ptr: info.ptr as _,
+                cookie: info.cookie as _,
+                has_strong_ref: info.has_strong_ref != 0,
+                has_weak_ref: info.has_weak_ref != 0,
+            }))
+        }
+    }
+
+    /// Freeze the binder of the process `pid`.
+    ///
+    /// Transactions to a frozen process fail with [`StatusCode::FrozenObject`] and
+    /// oneway transactions are queued until it is unfrozen.
+    /// The driver waits up to `timeout` for the outstanding transactions of the process
+    /// to complete and returns [`StatusCode::WouldBlock`] if they don't.
+    /// Freeze the process with cgroup freezer only after this call succeeds.
+    pub fn freeze(&self, pid: u32, timeout: std::time::Duration) -> Result<()> {
+        self.set_frozen(pid, true, timeout.as_millis().min(u32::MAX as _) as _)
+    }
+
+    /// Unfreeze the binder of the process `pid`.
+    pub fn unfreeze(&self, pid: u32) -> Result<()> {
+        self.set_frozen(pid, false, 0)
+    }
+
+    fn set_frozen(&self, pid: u32, enable: bool, timeout_ms: u32) -> Result<()> {
+        let info = binder::binder_freeze_info {
+            pid,
+            enable: enable as _,
+            timeout_ms,
+        };
+
+        unsafe {
+            binder::freeze(self.driver.as_raw_fd(), &info)
+                .map_err(|e| {
+                    log::error!("Binder ioctl to {} pid {} failed: {}",
+                        if enable { "freeze" } else { "unfreeze" }, pid, e);
+                    e
+                })?;
+        }
+        Ok(())
+    }
+
+    /// Query which transactions the process `pid` received while it was frozen.
+    pub fn frozen_info(&self, pid: u32) -> Result<FrozenInfo> {
+        let mut info = binder::binder_frozen_status_info {
+            pid,
+            sync_recv: 0,
+            async_recv: 0,
+        };
+
+        unsafe {
+            binder::get_frozen_info(self.driver.as_raw_fd(), &mut info)
+                .map_err(|e| {
+                    log::error!("Binder ioctl to get frozen info of pid {} failed: {}", pid, e);
+                    e
+                })?;
+        }
+
+        Ok(FrozenInfo {
+            sync_received: (info.sync_recv & FROZEN_SYNC_RECEIVED) != 0,
+            async_received: info.async_recv != 0,
+            sync_pending: (info.sync_recv & FROZEN_SYNC_PENDING) != 0,
+        })
+    }
+
+    /// Start the thread pool of the default ProcessState.
     pub fn start_thread_pool() {
-        let this = Self::as_self();
-        if this.thread_pool_started.compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed).is_ok() {
-            if this.max_threads == 0 {
+        Self::as_self().start_pool()
+    }
+
+    /// Start the thread pool of this ProcessState.
+    pub fn start_pool(&self) {
+        if self.thread_pool_started.compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed).is_ok() {
+            if self.max_threads == 0 {
                 log::warn!("Extra binder thread started, but 0 threads requested.\nDo not use *start_thread_pool when zero threads are requested.");
             }
-            this.spawn_pooled_thread(true);
+            self.spawn_pooled_thread(true);
         }
     }
 
     fn make_binder_thread_name(&self) -> String {
         let seq = self.thread_pool_seq.fetch_add(1, Ordering::SeqCst);
         let pid = std::process::id();
-        let driver_name = self.driver_name.file_name()
-            .and_then(|name| name.to_str())
-            .map(|name| name.to_owned())
-            .unwrap_or("BINDER".to_owned());
-        format!("{}:{}_{:X}", driver_name, pid, seq)
+        let prefix = match self.thread_name_prefix {
+            Some(ref prefix) => prefix.clone(),
+            None => self.driver_name.file_name()
+                .and_then(|name| name.to_str())
+                .map(|name| name.to_owned())
+                .unwrap_or("BINDER".to_owned()),
+        };
+        format!("{}:{}_{:X}", prefix, pid, seq)
     }
 
     pub(crate) fn spawn_pooled_thread(&self, is_main: bool) {
-        if self.thread_pool_started.load(Ordering::Relaxed) {
+        if self.thread_pool_started.load(Ordering::Relaxed) && !self.is_shutting_down() {
             let name = self.make_binder_thread_name();
             log::info!("Spawning new pooled thread, name={}", name);
+            let this = self.as_arc();
             let _ = thread::Builder::new().name(name).spawn(move || {
-                thread_state::join_thread_pool(is_main)
+                thread_state::enter(&this, || thread_state::join_thread_pool(is_main))
             });
 
             self.kernel_started_threads.fetch_add(1, Ordering::SeqCst);
@@ -268,17 +711,176 @@ impl ProcessState {
         // to return too high of a value.
     }
 
+    /// Join the current thread to the thread pool of the default ProcessState.
     pub fn join_thread_pool() -> Result<()> {
-        thread_state::join_thread_pool(true)
+        Self::as_self().join_pool()
+    }
+
+    /// Join the current thread to the thread pool of this ProcessState.
+    pub fn join_pool(&self) -> Result<()> {
+        thread_state::enter(&self.as_arc(), || thread_state::join_thread_pool(true))
+    }
+
+    /// Set up the current thread to handle the incoming commands by polling.
+    ///
+    /// The returned driver fd becomes readable when commands are pending for this thread.
+    /// Register it to an event loop like epoll, mio or calloop, and call
+    /// handle_polled_commands() whenever it is readable. The thread acts as a looper of
+    /// this ProcessState, so it doesn't need to join the thread pool.
+    ///
+    /// shutdown() doesn't know about the event loop unless it is registered by add_poller().
+    /// Register it before this call to be stopped by shutdown(), and call exit_polling() when
+    /// the event loop stops polling.
+    pub fn setup_polling(&self) -> Result<RawFd> {
+        thread_state::enter(&self.as_arc(), thread_state::setup_polling)?;
+        Ok(self.driver.as_raw_fd())
+    }
+
+    /// Handle all pending commands of the current thread. It must be called on the thread
+    /// which called setup_polling() when the driver fd is readable.
+    pub fn handle_polled_commands(&self) -> Result<()> {
+        thread_state::enter(&self.as_arc(), thread_state::handle_polled_commands)
+    }
+
+    /// Stop handling the incoming commands on the current thread set up by setup_polling().
+    pub fn exit_polling(&self) -> Result<()> {
+        thread_state::enter(&self.as_arc(), thread_state::exit_polling)
+    }
+
+    /// Whether shutdown() was called.
+    pub fn is_shutting_down(&self) -> bool {
+        self.shutting_down.load(Ordering::Acquire)
+    }
+
+    /// Whether shutdown() has released the resources of the binder driver.
+    pub(crate) fn is_shut_down(&self) -> bool {
+        self.shut_down.load(Ordering::Acquire)
+    }
+
+    /// Register an event loop polling for commands by setup_polling().
+    ///
+    /// `stop` is called by shutdown(), possibly more than once and from another thread, to
+    /// ask the event loop to stop polling. shutdown() waits until the returned [`Poller`] is
+    /// dropped. The event loop must check is_shutting_down() after the registration, not to
+    /// miss a call made before it.
+    pub fn add_poller(&self, stop: impl Fn() + Send + Sync + 'static) -> Poller {
+        let stop: StopPolling = Arc::new(stop);
+        self.pollers.lock().unwrap().push(stop.clone());
+        Poller { process: self.as_arc(), stop }
+    }
+
+    /// Stop the thread pool and release the resources of the binder driver.
+    ///
+    /// The threads in the thread pool, including the ones which called join_pool(),
+    /// leave it after handling the transactions they already received.
+    /// The threads polling for commands like `TokioPollingThread` are stopped as well.
+    /// If they don't leave within `timeout`, [`StatusCode::TimedOut`] is returned and
+    /// nothing is released, so that shutdown() can be called again.
+    /// Otherwise, the kernel references of all handles are released and the buffer is unmapped.
+    /// Binders obtained from this ProcessState fail with [`StatusCode::DeadObject`] after that.
+    ///
+    /// It can't be called from a binder thread of this ProcessState.
+    pub fn shutdown(&self, timeout: Duration) -> Result<()> {
+        let this = self.as_arc();
+        if thread_state::enter(&this, thread_state::is_looper) {
+            log::error!("shutdown() can't be called from a binder thread.");
+            return Err(StatusCode::InvalidOperation);
+        }
+
+        if self.shut_down.load(Ordering::Acquire) {
+            return Ok(());
+        }
+        self.shutting_down.store(true, Ordering::Release);
+
+        let deadline = Instant::now() + timeout;
+        loop {
+            let pollers = self.pollers.lock().unwrap().clone();
+            let threads = self.current_threads.load(Ordering::SeqCst) + pollers.len();
+            if threads == 0 {
+                break;
+            }
+
+            let now = Instant::now();
+            if now >= deadline {
+                log::warn!("{} binder threads are still running after {:?}.", threads, timeout);
+                return Err(StatusCode::TimedOut);
+            }
+
+            // Closing a file descriptor of the driver makes it wake up the threads waiting for work.
+            let fd = nix::unistd::dup(self.driver.as_raw_fd())?;
+            nix::unistd::close(fd)?;
+            // But it doesn't wake up the drivers polling for commands.
+            pollers.iter().for_each(|stop| stop());
+
+            thread::sleep(SHUTDOWN_POLL_INTERVAL.min(deadline - now));
+        }
+
+        thread_state::enter(&this, || -> Result<()> {
+            let handle_to_proxy = std::mem::take(&mut *self.handle_to_proxy.write().unwrap());
+            for weak in handle_to_proxy.values() {
+                if let Some(proxy) = weak.as_binder().as_proxy() {
+                    proxy.release_for_shutdown()?;
+                }
+            }
+            *self.context_manager.write().unwrap() = None;
+
+            thread_state::flush_commands()
+        })?;
+
+        self.shut_down.store(true, Ordering::Release);
+        thread_state::release_shut_down_thread_states();
+
+        // The Parcels received from the driver may be still alive, and other threads may be
+        // waiting for a reply. If so, the memory map is unmapped when the last of them is done.
+        if self.buffer_users.request_unmap() {
+            self.unmap()?;
+        } else {
+            log::info!("The binder buffers are still in use. Unmapping them is deferred.");
+        }
+
+        Ok(())
+    }
+
+    /// Register a thread which is going to receive a reply in a buffer of the memory map.
+    /// It fails after shutdown().
+    pub(crate) fn try_acquire_buffer(&self) -> Result<()> {
+        if self.buffer_users.try_acquire() {
+            Ok(())
+        } else {
+            Err(StatusCode::DeadObject)
+        }
+    }
+
+    /// Register a Parcel referring to a buffer of the memory map.
+    pub(crate) fn acquire_buffer(&self) {
+        self.buffer_users.acquire()
+    }
+
+    /// Unregister a user registered by try_acquire_buffer() or acquire_buffer().
+    pub(crate) fn release_buffer(&self) {
+        if self.buffer_users.release() {
+            if let Err(err) = self.unmap() {
+                log::error!("Failed to unmap the binder buffers: {}", err);
+            }
+        }
+    }
+
+    fn unmap(&self) -> Result<()> {
+        let mut mmap = self.mmap.write().unwrap();
+        if mmap.size != 0 {
+            unsafe { nix::sys::mman::munmap(mmap.ptr, mmap.size)?; }
+            mmap.size = 0;
+        }
+        Ok(())
     }
 }
 
-fn open_driver(driver: &Path, max_threads: u32) -> std::result::Result<File, Box<dyn std::error::Error>> {
+fn open_driver(driver: &Path, max_threads: u32, oneway_spam_detection: bool) -> InitResult<File> {
     let fd = File::options()
         .read(true)
         .write(true)
         .open(driver)
-        .map_err(|e| format!("Opening '{}' failed: {}\n", driver.to_string_lossy(), e))?;
+        .map_err(|e| format!("Opening '{}' failed: {}", driver.to_string_lossy(), e))?;
 
     let mut vers = binder::binder_version { protocol_version: 0 };
 
@@ -297,9 +899,9 @@ fn open_driver(driver: &Path, max_threads: u32) -> std::result::Result<File, Box
             .map_err(|e| format!("Binder ioctl to set max threads failed: {}", e))?;
         log::info!("Binder driver max threads set to {}", max_threads);
 
-        let enable = DEFAULT_ENABLE_ONEWAY_SPAM_DETECTION;
+        let enable = oneway_spam_detection as u32;
         if let Err(e) = binder::enable_oneway_spam_detection(raw_fd, &enable){
-            log::warn!("Binder ioctl to enable oneway spam detection failed: {}", e)
+            log::warn!("Binder ioctl to set oneway spam detection({}) failed: {}", oneway_spam_detection, e)
         }
     }
 
@@ -309,6 +911,32 @@ fn open_driver(driver: &Path, max_threads: u32) -> std::result::Result<File, Box
 impl Drop for ProcessState {
     fn drop(self: &mut ProcessState) {
         let mmap = self.mmap.write().unwrap();
-        unsafe { nix::sys::mman::munmap(mmap.ptr, mmap.size).unwrap(); }
+        if mmap.size != 0 {
+            unsafe { nix::sys::mman::munmap(mmap.ptr, mmap.size).unwrap(); }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_buffer_users() {
+        let users = BufferUsers::default();
+        assert!(users.try_acquire());
+        users.acquire();
+
+        // The last user unmaps it after the request.
+        assert!(!users.request_unmap());
+        assert!(!users.try_acquire());
+        assert!(!users.release());
+        // A reply of a waiting thread can still arrive.
+        users.acquire();
+        assert!(!users.release());
+        assert!(users.release());
+
+        let users = BufferUsers::default();
+        assert!(users.request_unmap());
     }
-}
\ No newline at end of file
+}
@@ -5,8 +5,8 @@ use std::any::Any;
 use std::fmt::{Debug, Formatter};
 use std::mem::ManuallyDrop;
 use std::os::fd::AsRawFd;
-use std::sync::atomic::AtomicBool;
-use std::sync::{Arc, RwLock};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, RwLock, Weak};
 
 use crate::{
     parcel::*,
@@ -15,6 +15,9 @@ use crate::{
     thread_state,
     ref_counter::RefCounter,
     binder_object::*,
+    process_state::ProcessState,
+    observer,
+    file_descriptor::ParcelFileDescriptor,
 };
 
 pub struct ProxyHandle {
@@ -26,10 +29,16 @@ pub struct ProxyHandle {
 
     strong: RefCounter,
     weak: RefCounter,
+
+    // The ProcessState owning the handle. It is weak to avoid a reference cycle
+    // with the handle cache of the ProcessState.
+    process: Weak<ProcessState>,
+    // The kernel references of the handle were released by ProcessState::shutdown().
+    released: AtomicBool,
 }
 
 impl ProxyHandle {
-    pub fn new(handle: u32, descriptor: &str, stability: Stability) -> Arc<Self> {
+    pub fn new(handle: u32, descriptor: &str, stability: Stability, process: &Arc<ProcessState>) -> Arc<Self> {
         Arc::new(Self {
             handle,
             descriptor: descriptor.to_owned(),
@@ -38,9 +47,41 @@ impl ProxyHandle {
             recipients: RwLock::new(Vec::new()),
             strong: Default::default(),
             weak: Default::default(),
+            process: Arc::downgrade(process),
+            released: AtomicBool::new(false),
         })
     }
 
+    /// Get the ProcessState which owns this handle.
+    pub fn process(&self) -> Result<Arc<ProcessState>> {
+        self.process.upgrade().ok_or(StatusCode::DeadObject)
+    }
+
+    // Talk to the binder driver through the ProcessState which owns this handle.
+    fn with_process<R>(&self, f: impl FnOnce() -> Result<R>) -> Result<R> {
+        if self.released.load(Ordering::Acquire) {
+            return Err(StatusCode::DeadObject);
+        }
+        let process = self.process()?;
+        thread_state::enter(&process, f)
+    }
+
+    // Release the kernel references of the handle. It must be called in the context of
+    // the owning ProcessState and the handle can't be used after that.
+    pub(crate) fn release_for_shutdown(&self) -> Result<()> {
+        if self.released.swap(true, Ordering::AcqRel) {
+            return Ok(());
+        }
+
+        if self.strong.is_held() {
+            thread_state::dec_strong_handle(self.handle())?;
+        }
+        if self.weak.is_held() {
+            thread_state::dec_weak_handle(self.handle())?;
+        }
+        Ok(())
+    }
+
     pub fn handle(&self) -> u32 {
         self.handle
     }
@@ -50,11 +91,17 @@ impl ProxyHandle {
     }
 
     pub fn submit_transact(&self, code: TransactionCode, data: &Parcel, flags: TransactionFlags) -> Result<Option<Parcel>> {
-        thread_state::transact(self.handle(), code, data, flags)
+        let observation = observer::Observation::outgoing(self.handle(), self.descriptor(), code, flags);
+        let result = self.with_process(|| thread_state::transact(self.handle(), code, data, flags));
+        observation.finish(data.data_size(),
+            result.as_ref().map_or(0, |reply| reply.as_ref().map_or(0, Parcel::data_size)),
+            result.as_ref().map(|_| ()).map_err(|err| *err));
+        result
     }
 
     pub fn prepare_transact(&self, write_header: bool) -> Result<Parcel> {
         let mut data = Parcel::new();
+        data.set_process(self.process()?);
 
         if write_header {
             data.write_interface_token(self.descriptor())?;
@@ -64,12 +111,14 @@ impl ProxyHandle {
     }
 
     pub(crate) fn send_obituary(&self, who: &WIBinder) -> Result<()> {
-        self.obituary_sent.store(true, std::sync::atomic::Ordering::Relaxed);
+        self.obituary_sent.store(true, Ordering::Relaxed);
 
         let recipients = self.recipients.read().unwrap();
         if !recipients.is_empty() {
-            thread_state::clear_death_notification(self.handle())?;
-            thread_state::flush_commands()?;
+            self.with_process(|| {
+                thread_state::clear_death_notification(self.handle())?;
+                thread_state::flush_commands()
+            })?;
         }
 
         for recipient in recipients.iter() {
@@ -81,6 +130,7 @@ impl ProxyHandle {
 
     pub fn dump<F: AsRawFd>(&self, fd: F, args: &[String]) -> Result<()> {
         let mut send = Parcel::new();
+        send.set_process(self.process()?);
         let obj = flat_binder_object::new_with_fd(fd.as_raw_fd(), true);
         send.write_object(&obj, true)?;
 
@@ -91,6 +141,47 @@ impl ProxyHandle {
         self.submit_transact(DUMP_TRANSACTION, &send, FLAG_CLEAR_BUF)?;
         Ok(())
     }
+
+    /// Invoke the shell command handler of the service with `args`.
+    /// The handler uses `stdin`, `stdout` and `stderr` as its standard streams.
+    /// The result of the handler is sent to `result_receiver` which implements IResultReceiver.
+    pub fn shell_command(&self, stdin: impl AsRawFd, stdout: impl AsRawFd, stderr: impl AsRawFd,
+        args: &[String], result_receiver: Option<&SIBinder>) -> Result<()> {
+        let mut send = Parcel::new();
+        send.set_process(self.process()?);
+        for fd in [stdin.as_raw_fd(), stdout.as_raw_fd(), stderr.as_raw_fd()] {
+            let obj = flat_binder_object::new_with_fd(fd, false);
+            send.write_object(&obj, true)?;
+        }
+
+        send.write::<i32>(&(args.len() as i32))?;
+        for arg in args {
+            send.write(arg)?;
+        }
+        // IShellCallback is not supported.
+        send.write::<Option<SIBinder>>(&None)?;
+        send.write(&result_receiver.cloned())?;
+        self.submit_transact(SHELL_COMMAND_TRANSACTION, &send, FLAG_CLEAR_BUF)?;
+        Ok(())
+    }
+
+    /// Ask the service to record the incoming transactions to `fd`
+    /// in the RecordedTransaction format.
+    pub fn start_recording(&self, fd: ParcelFileDescriptor) -> Result<()> {
+        let mut send = Parcel::new();
+        send.set_process(self.process()?);
+        send.write(&fd)?;
+        self.submit_transact(START_RECORDING_TRANSACTION, &send, FLAG_CLEAR_BUF)?;
+        Ok(())
+    }
+
+    /// Ask the service to stop recording the transactions.
+    pub fn stop_recording(&self) -> Result<()> {
+        let mut send = Parcel::new();
+        send.set_process(self.process()?);
+        self.submit_transact(STOP_RECORDING_TRANSACTION, &send, FLAG_CLEAR_BUF)?;
+        Ok(())
+    }
 }
 
 impl Debug for ProxyHandle {
@@ -113,13 +204,15 @@ impl PartialEq for ProxyHandle {
 impl IBinder for ProxyHandle {
     /// Register a death notification for this object.
     fn link_to_death(&self, recipient: Arc<dyn DeathRecipient>) -> Result<()> {
-        if self.obituary_sent.load(std::sync::atomic::Ordering::Relaxed) {
+        if self.obituary_sent.load(Ordering::Relaxed) {
             return Err(StatusCode::DeadObject);
         } else {
             let mut recipients = self.recipients.write().unwrap();
             if recipients.is_empty() {
-                thread_state::request_death_notification(self.handle())?;
-                thread_state::flush_commands()?;
+                self.with_process(|| {
+                    thread_state::request_death_notification(self.handle())?;
+                    thread_state::flush_commands()
+                })?;
             }
 
             recipients.push(recipient);
@@ -131,15 +224,17 @@ impl IBinder for ProxyHandle {
     /// The recipient will no longer be called if this object
     /// dies.
     fn unlink_to_death(&self, recipient: Arc<dyn DeathRecipient>) -> Result<()> {
-        if self.obituary_sent.load(std::sync::atomic::Ordering::Relaxed) {
+        if self.obituary_sent.load(Ordering::Relaxed) {
             return Err(StatusCode::DeadObject);
         } else {
             let mut recipients = self.recipients.write().unwrap();
 
             recipients.retain(|r| !Arc::ptr_eq(r, &recipient));
             if recipients.is_empty() {
-                thread_state::clear_death_notification(self.handle())?;
-                thread_state::flush_commands()?;
+                self.with_process(|| {
+                    thread_state::clear_death_notification(self.handle())?;
+                    thread_state::flush_commands()
+                })?;
             }
         }
         Ok(())
@@ -147,7 +242,15 @@ impl IBinder for ProxyHandle {
 
     /// Send a ping transaction to this object
     fn ping_binder(&self) -> Result<()> {
-        thread_state::ping_binder(self.handle())
+        self.with_process(|| thread_state::ping_binder(self.handle()))
+    }
+
+    fn get_extension(&self) -> Result<Option<SIBinder>> {
+        let data = self.prepare_transact(false)?;
+        match self.submit_transact(EXTENSION_TRANSACTION, &data, FLAG_CLEAR_BUF)? {
+            Some(mut reply) => reply.read(),
+            None => Ok(None),
+        }
     }
 
     // fn stability(&self) -> Stability {
@@ -174,13 +277,13 @@ impl IBinder for ProxyHandle {
         // In the Android implementation, it simultaneously increases the weak reference,
         // but until the necessity is confirmed, we will not support the related functionality here.
         self.strong.inc(|| {
-            thread_state::inc_strong_handle(self.handle(), strong.clone())
+            self.with_process(|| thread_state::inc_strong_handle(self.handle(), strong.clone()))
         })
     }
 
     fn attempt_inc_strong(&self) -> bool {
         self.strong.attempt_inc(false, || {
-                if let Err(err) = thread_state::attempt_inc_strong_handle(self.handle()) {
+                if let Err(err) = self.with_process(|| thread_state::attempt_inc_strong_handle(self.handle())) {
                     log::error!("Error in attempt_inc_strong_handle() is {:?}", err);
                     false
                 } else {
@@ -188,7 +291,7 @@ impl IBinder for ProxyHandle {
                 }
             },
             || {
-                thread_state::dec_strong_handle(self.handle())
+                self.with_process(|| thread_state::dec_strong_handle(self.handle()))
                     .expect("Failed to decrease the binder strong reference count.");
             }
         )
@@ -196,19 +299,25 @@ impl IBinder for ProxyHandle {
 
     fn dec_strong(&self, _strong: Option<ManuallyDrop<SIBinder>>) -> Result<()> {
         self.strong.dec(|| {
-            thread_state::dec_strong_handle(self.handle())
+            if self.released.load(Ordering::Acquire) {
+                return Ok(());
+            }
+            self.with_process(|| thread_state::dec_strong_handle(self.handle()))
         })
     }
 
     fn inc_weak(&self, weak: &WIBinder) -> Result<()> {
         self.weak.inc(|| {
-            thread_state::inc_weak_handle(self.handle(), weak)
+            self.with_process(|| thread_state::inc_weak_handle(self.handle(), weak))
         })
     }
 
     fn dec_weak(&self) -> Result<()> {
         self.weak.dec(|| {
-            thread_state::dec_weak_handle(self.handle())
+            if self.released.load(Ordering::Acquire) {
+                return Ok(());
+            }
+            self.with_process(|| thread_state::dec_weak_handle(self.handle()))
         })
     }
 }
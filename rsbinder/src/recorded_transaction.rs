@@ -0,0 +1,206 @@
+// Copyright 2022 Jeff Kim <hiking90@gmail.com>
+// SPDX-License-Identifier: Apache-2.0
+
+//! Recording of binder transactions in the RecordedTransaction format of Android.
+//!
+//! A recording is a sequence of transactions. Each transaction is a sequence of chunks
+//! which consist of a chunk descriptor, the chunk data padded to 8 bytes and a checksum.
+//! The checksum is the XOR of all 64-bit words of the descriptor and the padded data.
+
+use std::io::{Read, Write};
+use std::time::Duration;
+
+use crate::error::{Result, StatusCode};
+
+const HEADER_CHUNK: u32 = 1;
+const DATA_PARCEL_CHUNK: u32 = 2;
+const REPLY_PARCEL_CHUNK: u32 = 3;
+const INTERFACE_NAME_CHUNK: u32 = 4;
+const END_CHUNK: u32 = 0x00ffffff;
+
+const MAX_CHUNK_DATA_SIZE: usize = 0xfffffff0;
+
+// Size of TransactionHeader of Android.
+const TRANSACTION_HEADER_SIZE: usize = 32;
+
+#[inline]
+fn padding8(len: usize) -> usize {
+    (8 - (len % 8)) % 8
+}
+
+/// A transaction received by a binder service.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct RecordedTransaction {
+    pub interface_name: String,
+    pub code: u32,
+    pub flags: u32,
+    /// The status returned by the service.
+    pub returned_status: i32,
+    /// Non-zero for RPC binder. It is always zero for the kernel binder.
+    pub version: u32,
+    /// The time when the transaction was received since UNIX_EPOCH.
+    pub timestamp: Duration,
+    pub data: Vec<u8>,
+    pub reply: Vec<u8>,
+}
+
+impl RecordedTransaction {
+    /// Write the transaction to `writer`.
+    pub fn write_to<W: Write>(&self, writer: &mut W) -> Result<()> {
+        let mut header = Vec::with_capacity(TRANSACTION_HEADER_SIZE);
+        header.extend_from_slice(&self.code.to_ne_bytes());
+        header.extend_from_slice(&self.flags.to_ne_bytes());
+        header.extend_from_slice(&self.returned_status.to_ne_bytes());
+        header.extend_from_slice(&self.version.to_ne_bytes());
+        header.extend_from_slice(&(self.timestamp.as_secs() as i64).to_ne_bytes());
+        header.extend_from_slice(&(self.timestamp.subsec_nanos() as i32).to_ne_bytes());
+        header.extend_from_slice(&0i32.to_ne_bytes());
+
+        write_chunk(writer, HEADER_CHUNK, &header)?;
+        write_chunk(writer, INTERFACE_NAME_CHUNK, self.interface_name.as_bytes())?;
+        write_chunk(writer, DATA_PARCEL_CHUNK, &self.data)?;
+        write_chunk(writer, REPLY_PARCEL_CHUNK, &self.reply)?;
+        write_chunk(writer, END_CHUNK, &[])?;
+        writer.flush()?;
+
+        Ok(())
+    }
+
+    /// Read the next transaction from `reader`.
+    /// None is returned at the end of the recording.
+    pub fn read_from<R: Read>(reader: &mut R) -> Result<Option<Self>> {
+        let mut transaction = RecordedTransaction::default();
+        let mut has_header = false;
+
+        loop {
+            let (chunk_type, data) = match read_chunk(reader)? {
+                Some(chunk) => chunk,
+                None if !has_header => return Ok(None),
+                None => {
+                    log::error!("RecordedTransaction: unexpected end of the recording.");
+                    return Err(StatusCode::NotEnoughData);
+                }
+            };
+
+            match chunk_type {
+                HEADER_CHUNK => {
+                    if data.len() != TRANSACTION_HEADER_SIZE {
+                        log::error!("RecordedTransaction: invalid header size {}", data.len());
+                        return Err(StatusCode::BadValue);
+                    }
+                    transaction.code = u32::from_ne_bytes(data[0..4].try_into()?);
+                    transaction.flags = u32::from_ne_bytes(data[4..8].try_into()?);
+                    transaction.returned_status = i32::from_ne_bytes(data[8..12].try_into()?);
+                    transaction.version = u32::from_ne_bytes(data[12..16].try_into()?);
+                    let secs = i64::from_ne_bytes(data[16..24].try_into()?);
+                    let nanos = i32::from_ne_bytes(data[24..28].try_into()?);
+                    transaction.timestamp = Duration::new(secs as _, nanos as _);
+                    has_header = true;
+                }
+                INTERFACE_NAME_CHUNK => {
+                    transaction.interface_name = String::from_utf8(data).map_err(|_| StatusCode::BadValue)?;
+                }
+                DATA_PARCEL_CHUNK => transaction.data = data,
+                REPLY_PARCEL_CHUNK => transaction.reply = data,
+                END_CHUNK => {
+                    if !has_header {
+                        log::error!("RecordedTransaction: the transaction has no header.");
+                        return Err(StatusCode::BadValue);
+                    }
+                    return Ok(Some(transaction));
+                }
+                _ => {
+                    log::warn!("RecordedTransaction: unknown chunk type {} is ignored.", chunk_type);
+                }
+            }
+        }
+    }
+}
+
+fn checksum(words: &[u8]) -> u64 {
+    words.chunks_exact(8)
+        .fold(0, |checksum, word| checksum ^ u64::from_ne_bytes(word.try_into().unwrap()))
+}
+
+fn write_chunk<W: Write>(writer: &mut W, chunk_type: u32, data: &[u8]) -> Result<()> {
+    if data.len() > MAX_CHUNK_DATA_SIZE {
+        log::error!("RecordedTransaction: chunk data is too big: {}", data.len());
+        return Err(StatusCode::BadValue);
+    }
+
+    let mut buffer = Vec::with_capacity(8 + data.len() + padding8(data.len()));
+    buffer.extend_from_slice(&chunk_type.to_ne_bytes());
+    buffer.extend_from_slice(&(data.len() as u32).to_ne_bytes());
+    buffer.extend_from_slice(data);
+    buffer.resize(buffer.len() + padding8(data.len()), 0);
+
+    let checksum = checksum(&buffer);
+    writer.write_all(&buffer)?;
+    writer.write_all(&checksum.to_ne_bytes())?;
+
+    Ok(())
+}
+
+fn read_chunk<R: Read>(reader: &mut R) -> Result<Option<(u32, Vec<u8>)>> {
+    let mut descriptor = [0u8; 8];
+    match reader.read_exact(&mut descriptor) {
+        Ok(_) => {}
+        Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e.into()),
+    }
+
+    let chunk_type = u32::from_ne_bytes(descriptor[0..4].try_into()?);
+    let data_size = u32::from_ne_bytes(descriptor[4..8].try_into()?) as usize;
+    if data_size > MAX_CHUNK_DATA_SIZE {
+        log::error!("RecordedTransaction: chunk data is too big: {}", data_size);
+        return Err(StatusCode::BadValue);
+    }
+
+    let mut buffer = descriptor.to_vec();
+    buffer.resize(8 + data_size + padding8(data_size), 0);
+    reader.read_exact(&mut buffer[8..])?;
+
+    let mut expected = [0u8; 8];
+    reader.read_exact(&mut expected)?;
+    if checksum(&buffer) != u64::from_ne_bytes(expected) {
+        log::error!("RecordedTransaction: checksum mismatch of chunk type {}", chunk_type);
+        return Err(StatusCode::BadValue);
+    }
+
+    buffer.truncate(8 + data_size);
+    Ok(Some((chunk_type, buffer.split_off(8))))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_recorded_transaction() -> Result<()> {
+        let transaction = RecordedTransaction {
+            interface_name: "my.hello".to_owned(),
+            code: 1,
+            flags: 0x10,
+            returned_status: 0,
+            version: 0,
+            timestamp: Duration::new(1234, 5678),
+            data: vec![1, 2, 3, 4, 5],
+            reply: vec![0; 12],
+        };
+
+        let mut recording = Vec::new();
+        transaction.write_to(&mut recording)?;
+        transaction.write_to(&mut recording)?;
+        assert_eq!(recording.len() % 8, 0);
+
+        let mut reader = recording.as_slice();
+        assert_eq!(RecordedTransaction::read_from(&mut reader)?, Some(transaction.clone()));
+        assert_eq!(RecordedTransaction::read_from(&mut reader)?, Some(transaction));
+        assert_eq!(RecordedTransaction::read_from(&mut reader)?, None);
+
+        recording[20] ^= 0xff;
+        assert_eq!(RecordedTransaction::read_from(&mut recording.as_slice()), Err(StatusCode::BadValue));
+
+        Ok(())
+    }
+}
@@ -77,6 +77,12 @@ impl RefCounter {
         Ok(())
     }
 
+    /// Whether the counter has been increased and is not released yet.
+    pub fn is_held(&self) -> bool {
+        let c = self.count.load(Ordering::Relaxed);
+        c > 0 && c != INITIAL_STRONG_VALUE
+    }
+
     // pub fn get(&self) -> i32 {
     //     self.count.load(Ordering::Relaxed)
     // }
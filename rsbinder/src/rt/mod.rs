@@ -1,3 +1,5 @@
+mod std_rt;
+pub use std_rt::*;
 #[cfg(feature = "tokio")]
 mod tokio_rt;
 #[cfg(feature = "tokio")]
@@ -0,0 +1,356 @@
+// Copyright 2022 Jeff Kim <hiking90@gmail.com>
+// SPDX-License-Identifier: Apache-2.0
+
+//! A dependency-free implementation of [`BinderAsyncPool`] and [`BinderAsyncRuntime`]
+//! backed by small std thread pools and a simple executor.
+//!
+//! ```text
+//! use rsbinder::{StdPool, StdRuntime};
+//!
+//! let proxy = hub::get_interface::<dyn IFooAsync<StdPool>>("...")?;
+//! let service = BnFoo::new_async_binder(FooService, StdRuntime::new()?);
+//! ```
+
+use crate::{BinderAsyncPool, BinderAsyncRuntime, BoxFuture, StatusCode};
+use std::future::Future;
+use std::pin::pin;
+use std::collections::VecDeque;
+use std::sync::{Arc, Condvar, Mutex, OnceLock};
+use std::task::{Context, Poll, Wake, Waker};
+use std::thread::{self, Thread};
+
+/// The default number of threads of the thread pools.
+pub const DEFAULT_STD_POOL_THREADS: usize = 4;
+
+type Job = Box<dyn FnOnce() + Send + 'static>;
+
+struct PoolState {
+    jobs: VecDeque<Job>,
+    // The futures waiting for a free slot of the queue.
+    waiters: Vec<Waker>,
+    closed: bool,
+}
+
+struct PoolQueue {
+    state: Mutex<PoolState>,
+    capacity: usize,
+    // Notified when a job is queued.
+    queued: Condvar,
+    // Notified when a job is taken from the queue.
+    taken: Condvar,
+}
+
+// A fixed size thread pool with a bounded queue.
+struct ThreadPool {
+    queue: Arc<PoolQueue>,
+}
+
+impl ThreadPool {
+    fn new(name: &str, threads: usize) -> std::io::Result<Self> {
+        let threads = threads.max(1);
+        let queue = Arc::new(PoolQueue {
+            state: Mutex::new(PoolState { jobs: VecDeque::new(), waiters: Vec::new(), closed: false }),
+            capacity: threads,
+            queued: Condvar::new(),
+            taken: Condvar::new(),
+        });
+
+        for i in 0..threads {
+            let queue = queue.clone();
+            thread::Builder::new()
+                .name(format!("{}:{}", name, i))
+                .spawn(move || Self::run(&queue))?;
+        }
+
+        Ok(Self { queue })
+    }
+
+    fn run(queue: &PoolQueue) {
+        loop {
+            let job = {
+                let mut state = queue.state.lock().unwrap();
+                loop {
+                    if let Some(job) = state.jobs.pop_front() {
+                        break job;
+                    }
+                    // The pool was dropped and all queued jobs are done.
+                    if state.closed {
+                        return;
+                    }
+                    state = queue.queued.wait(state).unwrap();
+                }
+            };
+
+            let waiters = std::mem::take(&mut queue.state.lock().unwrap().waiters);
+            waiters.into_iter().for_each(Waker::wake);
+            queue.taken.notify_one();
+
+            // The lock is released before the job runs.
+            job();
+        }
+    }
+
+    // Queue a job without blocking. If the queue is full, the job is returned and
+    // `waker` is woken when a slot may be available.
+    fn try_execute(&self, job: Job, waker: &Waker) -> std::result::Result<(), Job> {
+        let mut state = self.queue.state.lock().unwrap();
+        if state.jobs.len() >= self.queue.capacity {
+            state.waiters.push(waker.clone());
+            return Err(job);
+        }
+        state.jobs.push_back(job);
+        self.queue.queued.notify_one();
+        Ok(())
+    }
+
+    // Queue a job. It blocks while the queue is full.
+    fn execute(&self, job: Job) {
+        let mut state = self.queue.state.lock().unwrap();
+        while state.jobs.len() >= self.queue.capacity {
+            state = self.queue.taken.wait(state).unwrap();
+        }
+        state.jobs.push_back(job);
+        self.queue.queued.notify_one();
+    }
+}
+
+impl Drop for ThreadPool {
+    fn drop(&mut self) {
+        self.queue.state.lock().unwrap().closed = true;
+        self.queue.queued.notify_all();
+    }
+}
+
+// Wake up the thread blocked in block_on().
+struct ThreadWaker(Thread);
+
+impl Wake for ThreadWaker {
+    fn wake(self: Arc<Self>) {
+        self.0.unpark();
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        self.0.unpark();
+    }
+}
+
+// Run a future to completion on the current thread.
+fn block_on<F: Future>(future: F) -> F::Output {
+    let mut future = pin!(future);
+    let waker = Waker::from(Arc::new(ThreadWaker(thread::current())));
+    let mut context = Context::from_waker(&waker);
+
+    loop {
+        match future.as_mut().poll(&mut context) {
+            Poll::Ready(output) => return output,
+            Poll::Pending => thread::park(),
+        }
+    }
+}
+
+// The result of a job which is sent from the pool to the awaiting future.
+struct Shared<T> {
+    value: Option<T>,
+    waker: Option<Waker>,
+    closed: bool,
+}
+
+struct JobHandle<T>(Arc<Mutex<Shared<T>>>);
+
+impl<T> Future for JobHandle<T> {
+    type Output = std::result::Result<T, StatusCode>;
+
+    fn poll(self: std::pin::Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut shared = self.0.lock().unwrap();
+        if let Some(value) = shared.value.take() {
+            Poll::Ready(Ok(value))
+        } else if shared.closed {
+            // The job panicked.
+            Poll::Ready(Err(StatusCode::FailedTransaction))
+        } else {
+            shared.waker = Some(cx.waker().clone());
+            Poll::Pending
+        }
+    }
+}
+
+// Mark the result closed even if the job panics, not to leave the future pending forever.
+struct JobGuard<T>(Arc<Mutex<Shared<T>>>);
+
+impl<T> Drop for JobGuard<T> {
+    fn drop(&mut self) {
+        let mut shared = self.0.lock().unwrap_or_else(|e| e.into_inner());
+        shared.closed = true;
+        if let Some(waker) = shared.waker.take() {
+            waker.wake();
+        }
+    }
+}
+
+fn blocking_pool() -> std::result::Result<&'static ThreadPool, StatusCode> {
+    static POOL: OnceLock<std::io::Result<ThreadPool>> = OnceLock::new();
+    POOL.get_or_init(|| ThreadPool::new("binder:std", DEFAULT_STD_POOL_THREADS))
+        .as_ref()
+        .map_err(|e| {
+            log::error!("Failed to spawn the threads of StdPool: {}", e);
+            StatusCode::from(std::io::Error::from(e.kind()))
+        })
+}
+
+/// Use a std thread pool for the blocking binder transactions of async interfaces.
+pub enum StdPool {}
+
+impl BinderAsyncPool for StdPool {
+    fn spawn<'a, F1, F2, Fut, A, B, E>(spawn_me: F1, after_spawn: F2) -> BoxFuture<'a, Result<B, E>>
+    where
+        F1: FnOnce() -> A,
+        F2: FnOnce(A) -> Fut,
+        Fut: Future<Output = Result<B, E>>,
+        F1: Send + 'static,
+        F2: Send + 'a,
+        Fut: Send + 'a,
+        A: Send + 'static,
+        B: Send + 'a,
+        E: From<crate::StatusCode>,
+    {
+        if crate::is_handling_transaction() {
+            // Run the transaction on the current binder thread for the deadlock prevention
+            // of the binder driver. See the comment of the BinderAsyncPool impl for Tokio.
+            let result = spawn_me();
+            return Box::pin(after_spawn(result));
+        }
+
+        let shared = Arc::new(Mutex::new(Shared { value: None, waker: None, closed: false }));
+        let handle = JobHandle(shared.clone());
+        let mut job: Option<Job> = Some(Box::new(move || {
+            let guard = JobGuard(shared);
+            let value = spawn_me();
+            guard.0.lock().unwrap().value = Some(value);
+        }));
+
+        Box::pin(async move {
+            // The job is queued by the future, so the executor isn't blocked while the queue is full.
+            std::future::poll_fn(|cx| {
+                let pool = blocking_pool()?;
+                match pool.try_execute(job.take().expect("The job is already queued."), cx.waker()) {
+                    Ok(()) => Poll::Ready(Ok(())),
+                    Err(rejected) => {
+                        job = Some(rejected);
+                        Poll::Pending
+                    }
+                }
+            }).await?;
+            let result = handle.await?;
+            after_spawn(result).await
+        })
+    }
+}
+
+/// A runtime for async binder servers without any dependency.
+///
+/// [`BinderAsyncRuntime::block_on`] runs the future on the calling binder thread, and the
+/// handlers of oneway transactions are run by the thread pool of the runtime. At most as many
+/// oneway handlers as the threads of the pool run at the same time.
+pub struct StdRuntime {
+    pool: ThreadPool,
+}
+
+impl StdRuntime {
+    /// Create a runtime with [`DEFAULT_STD_POOL_THREADS`] threads for oneway transactions.
+    pub fn new() -> std::io::Result<Self> {
+        Self::with_threads(DEFAULT_STD_POOL_THREADS)
+    }
+
+    /// Create a runtime with `threads` threads for oneway transactions.
+    pub fn with_threads(threads: usize) -> std::io::Result<Self> {
+        Ok(Self {
+            pool: ThreadPool::new("binder:std_rt", threads)?,
+        })
+    }
+}
+
+impl BinderAsyncRuntime for StdRuntime {
+    fn block_on<F: Future>(&self, future: F) -> F::Output {
+        block_on(future)
+    }
+
+    fn spawn_oneway(&self, future: BoxFuture<'static, ()>) {
+        self.pool.execute(Box::new(move || block_on(future)));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::mpsc::channel;
+    use std::time::Duration;
+
+    #[test]
+    fn test_std_runtime() {
+        let runtime = StdRuntime::with_threads(2).unwrap();
+
+        // A future which is woken from another thread.
+        let shared = Arc::new(Mutex::new(Shared { value: None, waker: None, closed: false }));
+        let handle = JobHandle(shared.clone());
+        thread::spawn(move || {
+            thread::sleep(Duration::from_millis(10));
+            let guard = JobGuard(shared);
+            guard.0.lock().unwrap().value = Some(7);
+        });
+        assert_eq!(runtime.block_on(handle), Ok(7));
+
+        let (sender, receiver) = channel();
+        for i in 0..4 {
+            let sender = sender.clone();
+            runtime.spawn_oneway(Box::pin(async move {
+                sender.send(i).unwrap();
+            }));
+        }
+        let mut received: Vec<i32> = (0..4).map(|_| receiver.recv().unwrap()).collect();
+        received.sort();
+        assert_eq!(received, vec![0, 1, 2, 3]);
+
+        // The result of a panicked job is an error.
+        let shared = Arc::new(Mutex::new(Shared::<i32> { value: None, waker: None, closed: false }));
+        drop(JobGuard(shared.clone()));
+        assert_eq!(block_on(JobHandle(shared)), Err(StatusCode::FailedTransaction));
+    }
+
+    #[test]
+    fn test_try_execute_full_queue() {
+        struct Flag(AtomicBool);
+        impl Wake for Flag {
+            fn wake(self: Arc<Self>) {
+                self.0.store(true, Ordering::SeqCst);
+            }
+        }
+
+        let pool = ThreadPool::new("test:std_rt", 1).unwrap();
+        let (sender, receiver) = channel::<()>();
+        let (started_sender, started) = channel::<()>();
+        // Occupy the thread, and then the queue.
+        pool.execute(Box::new(move || {
+            started_sender.send(()).unwrap();
+            receiver.recv().unwrap();
+        }));
+        started.recv().unwrap();
+        pool.execute(Box::new(|| {}));
+
+        let flag = Arc::new(Flag(AtomicBool::new(false)));
+        let waker = Waker::from(flag.clone());
+        let job = pool.try_execute(Box::new(|| {}), &waker).expect_err("The queue is full.");
+        assert!(!flag.0.load(Ordering::SeqCst));
+
+        // The waiter is woken when the queued job is taken.
+        sender.send(()).unwrap();
+        while !flag.0.load(Ordering::SeqCst) {
+            thread::sleep(Duration::from_millis(1));
+        }
+        let mut job = Some(job);
+        while let Err(rejected) = pool.try_execute(job.take().unwrap(), &waker) {
+            job = Some(rejected);
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+}
@@ -29,10 +29,14 @@
 //! rsbinder::get_interface::<dyn SomeAsyncInterface<Tokio>>("...").
 //! ```
 //!
+//! [`TokioPollingThread`] waits for the incoming commands of a ProcessState with the readiness
+//! of the binder driver fd instead of the blocking thread pool.
+//!
 //! [`Tokio`]: crate::Tokio
 
 use crate::{hub, BinderAsyncRuntime, BinderAsyncPool, BoxFuture, FromIBinder, StatusCode, Strong};
 use std::future::Future;
+use std::sync::Arc;
 
 /// Retrieve an existing service for a particular interface, sleeping for a few
 /// seconds if it doesn't yet exist.
@@ -58,29 +62,52 @@ pub async fn get_interface<T: FromIBinder + ?Sized + 'static>(
     }
 }
 
-// /// Retrieve an existing service for a particular interface, or start it if it
-// /// is configured as a dynamic service and isn't yet started.
-// pub async fn wait_for_interface<T: FromIBinder + ?Sized + 'static>(
-//     name: &str,
-// ) -> Result<Strong<T>, StatusCode> {
-//     if rsbinder::is_handling_transaction() {
-//         // See comment in the BinderAsyncPool impl.
-//         return rsbinder::wait_for_interface::<T>(name);
-//     }
-
-//     let name = name.to_string();
-//     let res = tokio::task::spawn_blocking(move || rsbinder::wait_for_interface::<T>(&name)).await;
-
-//     // The `is_panic` branch is not actually reachable in Android as we compile
-//     // with `panic = abort`.
-//     match res {
-//         Ok(Ok(service)) => Ok(service),
-//         Ok(Err(err)) => Err(err),
-//         Err(e) if e.is_panic() => std::panic::resume_unwind(e.into_panic()),
-//         Err(e) if e.is_cancelled() => Err(StatusCode::FailedTransaction),
-//         Err(_) => Err(StatusCode::Unknown),
-//     }
-// }
+/// Retrieve a service for a particular interface, waiting until it is registered
+/// or `timeout` expires. See [`hub::wait_for_service`].
+pub async fn wait_for_interface<T: FromIBinder + ?Sized + 'static>(
+    name: &str,
+    timeout: Option<std::time::Duration>,
+) -> Result<Strong<T>, StatusCode> {
+    if crate::is_handling_transaction() {
+        // See comment in the BinderAsyncPool impl.
+        return hub::wait_for_interface::<T>(name, timeout);
+    }
+
+    let name = name.to_string();
+    let res = tokio::task::spawn_blocking(move || hub::wait_for_interface::<T>(&name, timeout)).await;
+
+    // The `is_panic` branch is not actually reachable in Android as we compile
+    // with `panic = abort`.
+    match res {
+        Ok(Ok(service)) => Ok(service),
+        Ok(Err(err)) => Err(err),
+        Err(e) if e.is_panic() => std::panic::resume_unwind(e.into_panic()),
+        Err(e) if e.is_cancelled() => Err(StatusCode::FailedTransaction),
+        Err(_) => Err(StatusCode::Unknown),
+    }
+}
+
+/// Retrieve a service, waiting until it is registered or `timeout` expires.
+/// See [`hub::wait_for_service`].
+pub async fn wait_for_service(
+    name: &str,
+    timeout: Option<std::time::Duration>,
+) -> Result<crate::SIBinder, StatusCode> {
+    if crate::is_handling_transaction() {
+        // See comment in the BinderAsyncPool impl.
+        return hub::wait_for_service(name, timeout);
+    }
+
+    let name = name.to_string();
+    let res = tokio::task::spawn_blocking(move || hub::wait_for_service(&name, timeout)).await;
+
+    match res {
+        Ok(result) => result,
+        Err(e) if e.is_panic() => std::panic::resume_unwind(e.into_panic()),
+        Err(e) if e.is_cancelled() => Err(StatusCode::FailedTransaction),
+        Err(_) => Err(StatusCode::Unknown),
+    }
+}
 
 /// Use the Tokio `spawn_blocking` pool with AIDL.
 pub enum Tokio {}
@@ -124,22 +151,149 @@ impl BinderAsyncPool for Tokio {
 }
 
 /// Wrapper around Tokio runtime types for providing a runtime to a binder server.
+///
+/// The handlers of oneway transactions are spawned on the runtime. See [`crate::OnewayQueue`]
+/// for their order and [`crate::set_max_oneway_tasks`] for their limit.
 pub struct TokioRuntime<R>(pub R);
 
 impl BinderAsyncRuntime for TokioRuntime<tokio::runtime::Runtime> {
     fn block_on<F: Future>(&self, future: F) -> F::Output {
         self.0.block_on(future)
     }
+
+    fn spawn_oneway(&self, future: BoxFuture<'static, ()>) {
+        self.0.spawn(future);
+    }
 }
 
 impl BinderAsyncRuntime for TokioRuntime<std::sync::Arc<tokio::runtime::Runtime>> {
     fn block_on<F: Future>(&self, future: F) -> F::Output {
         self.0.block_on(future)
     }
+
+    fn spawn_oneway(&self, future: BoxFuture<'static, ()>) {
+        self.0.spawn(future);
+    }
 }
 
 impl BinderAsyncRuntime for TokioRuntime<tokio::runtime::Handle> {
     fn block_on<F: Future>(&self, future: F) -> F::Output {
         self.0.block_on(future)
     }
+
+    fn spawn_oneway(&self, future: BoxFuture<'static, ()>) {
+        self.0.spawn(future);
+    }
+}
+
+/// A thread which polls for the incoming binder commands of a ProcessState with tokio's
+/// `AsyncFd`, instead of blocking in the driver like the threads of the thread pool.
+///
+/// It is a readiness-driven polling helper, not a driver on the tokio runtime of the
+/// application. The binder driver delivers commands to the thread which set up polling,
+/// and a reply must be sent from the thread which received the transaction. So the
+/// helper runs on a dedicated thread with a current-thread runtime, which only waits for
+/// the readiness of the driver fd. The transactions are handled and replied synchronously
+/// on that thread outside of the runtime, one by one. Async services still run their
+/// handlers on their own runtime through [`TokioRuntime`].
+///
+/// The thread is not counted as a thread of the thread pool.
+/// [`ProcessState::shutdown()`](crate::ProcessState::shutdown) stops it and waits for it to exit.
+pub struct TokioPollingThread {
+    thread: std::thread::JoinHandle<crate::Result<()>>,
+}
+
+impl TokioPollingThread {
+    /// Spawn the polling thread for `process`.
+    /// It runs until `process` is shut down or an error occurs.
+    pub fn spawn(process: &crate::ProcessState) -> std::io::Result<Self> {
+        let process = process.as_arc();
+        let thread = std::thread::Builder::new()
+            .name(format!("{}:tokio", process.driver_name().display()))
+            .spawn(move || crate::thread_state::enter(&process, || Self::run(&process)))?;
+
+        Ok(Self { thread })
+    }
+
+    /// Wait for the polling thread to finish.
+    pub fn join(self) -> crate::Result<()> {
+        self.thread.join().unwrap_or_else(|e| std::panic::resume_unwind(e))
+    }
+
+    fn run(process: &crate::ProcessState) -> crate::Result<()> {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_io()
+            .build()?;
+
+        let stop = Arc::new(tokio::sync::Notify::new());
+        let _poller = {
+            let stop = stop.clone();
+            process.add_poller(move || stop.notify_one())
+        };
+
+        let fd = process.setup_polling()?;
+        // The fd must be registered on this thread to get the readiness of this thread.
+        let async_fd = {
+            let _guard = runtime.enter();
+            tokio::io::unix::AsyncFd::with_interest(fd, tokio::io::Interest::READABLE)?
+        };
+
+        while !process.is_shutting_down() && Self::wait_readable(&runtime, &async_fd, &stop)? {
+            process.handle_polled_commands()?;
+        }
+
+        process.exit_polling()
+    }
+
+    // Wait until the fd is readable or the thread is stopped. Returns false if it is stopped.
+    fn wait_readable<T: std::os::fd::AsRawFd>(runtime: &tokio::runtime::Runtime,
+        async_fd: &tokio::io::unix::AsyncFd<T>, stop: &tokio::sync::Notify) -> std::io::Result<bool> {
+        runtime.block_on(async {
+            tokio::select! {
+                readable = async_fd.readable() => {
+                    // Clear the readiness first not to miss the commands arriving while handling.
+                    readable?.clear_ready();
+                    Ok(true)
+                }
+                _ = stop.notified() => Ok(false),
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use std::os::unix::net::UnixStream;
+
+    #[test]
+    fn test_wait_readable() {
+        let runtime = tokio::runtime::Builder::new_current_thread().enable_io().build().unwrap();
+        let (mut writer, reader) = UnixStream::pair().unwrap();
+        reader.set_nonblocking(true).unwrap();
+        let async_fd = {
+            let _guard = runtime.enter();
+            tokio::io::unix::AsyncFd::with_interest(reader, tokio::io::Interest::READABLE).unwrap()
+        };
+        let stop = Arc::new(tokio::sync::Notify::new());
+
+        writer.write_all(&[0]).unwrap();
+        assert!(TokioPollingThread::wait_readable(&runtime, &async_fd, &stop).unwrap());
+
+        // The stop request from another thread wakes up the waiting thread.
+        let notifier = {
+            let stop = stop.clone();
+            std::thread::spawn(move || {
+                std::thread::sleep(std::time::Duration::from_millis(10));
+                stop.notify_one();
+            })
+        };
+        assert!(!TokioPollingThread::wait_readable(&runtime, &async_fd, &stop).unwrap());
+        notifier.join().unwrap();
+
+        // A stop request made before waiting is not missed.
+        stop.notify_one();
+        assert!(!TokioPollingThread::wait_readable(&runtime, &async_fd, &stop).unwrap());
+    }
 }
@@ -0,0 +1,207 @@
+// Copyright 2022 Jeff Kim <hiking90@gmail.com>
+// SPDX-License-Identifier: Apache-2.0
+
+//! Statistics of the binder transactions of the process.
+//!
+//! Counters and latency histograms are kept for every pair of interface descriptor and
+//! transaction code, separately for outgoing and incoming transactions. The statistics of
+//! incoming transactions are appended to the dump of each local binder.
+//!
+//! The collection is disabled by default, because it takes a global lock and reads the clock
+//! for every transaction. Enable it with [`set_enabled`].
+
+use std::collections::HashMap;
+use std::io::Write;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+use crate::{
+    binder::TransactionCode,
+    observer::{TransactionDirection, TransactionEvent},
+};
+
+/// The upper bounds of the latency histogram buckets in microseconds.
+/// The last bucket of the histogram counts the transactions slower than all of them.
+pub const LATENCY_BUCKETS_US: [u64; 12] = [
+    50, 100, 250, 500, 1_000, 2_500, 5_000, 10_000, 25_000, 100_000, 500_000, 1_000_000,
+];
+
+/// Statistics of a transaction code of an interface.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct MethodStats {
+    pub count: u64,
+    /// The number of transactions that failed.
+    pub errors: u64,
+    pub data_bytes: u64,
+    pub reply_bytes: u64,
+    pub total_duration: Duration,
+    pub max_duration: Duration,
+    /// The counts of transactions for each bucket of LATENCY_BUCKETS_US and the overflow.
+    pub histogram: [u64; LATENCY_BUCKETS_US.len() + 1],
+}
+
+impl MethodStats {
+    /// The average latency of the transactions.
+    pub fn average_duration(&self) -> Duration {
+        if self.count == 0 {
+            Duration::ZERO
+        } else {
+            Duration::from_nanos((self.total_duration.as_nanos() / self.count as u128) as u64)
+        }
+    }
+
+    fn record(&mut self, event: &TransactionEvent) {
+        self.count += 1;
+        if event.result.is_err() {
+            self.errors += 1;
+        }
+        self.data_bytes += event.data_size as u64;
+        self.reply_bytes += event.reply_size as u64;
+        self.total_duration += event.duration;
+        self.max_duration = self.max_duration.max(event.duration);
+
+        let micros = event.duration.as_micros() as u64;
+        let bucket = LATENCY_BUCKETS_US.iter()
+            .position(|bound| micros <= *bound)
+            .unwrap_or(LATENCY_BUCKETS_US.len());
+        self.histogram[bucket] += 1;
+    }
+}
+
+/// Statistics of a transaction code of an interface in a direction.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TransactionStats {
+    pub direction: TransactionDirection,
+    pub descriptor: String,
+    pub code: TransactionCode,
+    pub stats: MethodStats,
+}
+
+type StatsMap = HashMap<String, HashMap<(TransactionDirection, TransactionCode), MethodStats>>;
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+static STATS: Mutex<Option<StatsMap>> = Mutex::new(None);
+
+/// Enable or disable the collection of the statistics. It is disabled by default.
+pub fn set_enabled(enabled: bool) {
+    ENABLED.store(enabled, Ordering::Relaxed);
+}
+
+pub fn is_enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+pub(crate) fn record(event: &TransactionEvent) {
+    let mut stats = STATS.lock().unwrap();
+    let stats = stats.get_or_insert_with(HashMap::new);
+
+    let methods = match stats.get_mut(event.descriptor) {
+        Some(methods) => methods,
+        None => stats.entry(event.descriptor.to_owned()).or_default(),
+    };
+    methods.entry((event.direction, event.code)).or_default().record(event);
+}
+
+/// Get the statistics of all interfaces sorted by descriptor, direction and code.
+pub fn snapshot() -> Vec<TransactionStats> {
+    let mut snapshot = Vec::new();
+    if let Some(stats) = STATS.lock().unwrap().as_ref() {
+        for (descriptor, methods) in stats {
+            for ((direction, code), stats) in methods {
+                snapshot.push(TransactionStats {
+                    direction: *direction,
+                    descriptor: descriptor.clone(),
+                    code: *code,
+                    stats: stats.clone(),
+                });
+            }
+        }
+    }
+    snapshot.sort_by(|a, b| {
+        (&a.descriptor, a.direction as u8, a.code).cmp(&(&b.descriptor, b.direction as u8, b.code))
+    });
+    snapshot
+}
+
+/// Get the statistics of an interface.
+pub fn interface_stats(descriptor: &str) -> Vec<TransactionStats> {
+    snapshot().into_iter()
+        .filter(|stats| stats.descriptor == descriptor)
+        .collect()
+}
+
+/// Clear all statistics.
+pub fn reset() {
+    *STATS.lock().unwrap() = None;
+}
+
+/// Write the statistics of an interface in the given direction in a human readable form.
+pub fn write_stats(writer: &mut dyn Write, descriptor: &str, direction: TransactionDirection) -> std::io::Result<()> {
+    let stats: Vec<_> = interface_stats(descriptor).into_iter()
+        .filter(|stats| stats.direction == direction)
+        .collect();
+    if stats.is_empty() {
+        return Ok(());
+    }
+
+    writeln!(writer, "{:?} transaction stats of {}:", direction, descriptor)?;
+    for stats in stats {
+        let method = &stats.stats;
+        writeln!(writer, "  code {}: count {}, errors {}, data {} bytes, reply {} bytes, avg {} us, max {} us",
+            stats.code, method.count, method.errors, method.data_bytes, method.reply_bytes,
+            method.average_duration().as_micros(), method.max_duration.as_micros())?;
+
+        let mut histogram = String::new();
+        for (i, count) in method.histogram.iter().enumerate().filter(|(_, count)| **count != 0) {
+            match LATENCY_BUCKETS_US.get(i) {
+                Some(bound) => histogram += &format!(" <={}us:{}", bound, count),
+                None => histogram += &format!(" >{}us:{}", LATENCY_BUCKETS_US[i - 1], count),
+            }
+        }
+        writeln!(writer, "    latency{}", histogram)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stats() {
+        let event = |code, duration, result| TransactionEvent {
+            direction: TransactionDirection::Incoming,
+            handle: None,
+            descriptor: "test.stats",
+            code,
+            flags: 0,
+            data_size: 8,
+            reply_size: 4,
+            duration,
+            result,
+        };
+
+        record(&event(1, Duration::from_micros(10), Ok(())));
+        record(&event(1, Duration::from_micros(300), Err(crate::StatusCode::BadValue)));
+        record(&event(2, Duration::from_secs(2), Ok(())));
+
+        let stats = interface_stats("test.stats");
+        assert_eq!(stats.len(), 2);
+        assert_eq!(stats[0].code, 1);
+        assert_eq!(stats[0].stats.count, 2);
+        assert_eq!(stats[0].stats.errors, 1);
+        assert_eq!(stats[0].stats.data_bytes, 16);
+        assert_eq!(stats[0].stats.max_duration, Duration::from_micros(300));
+        assert_eq!(stats[0].stats.average_duration(), Duration::from_micros(155));
+        assert_eq!(stats[0].stats.histogram[0], 1);
+        assert_eq!(stats[0].stats.histogram[3], 1);
+        assert_eq!(stats[1].stats.histogram[LATENCY_BUCKETS_US.len()], 1);
+
+        let mut output = Vec::new();
+        write_stats(&mut output, "test.stats", TransactionDirection::Incoming).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.contains("code 1: count 2, errors 1"));
+        assert!(output.contains(">1000000us:1"));
+    }
+}
@@ -20,6 +20,7 @@
 use std::sync::{atomic::Ordering, Arc};
 use std::os::unix::io::AsRawFd;
 use std::cell::RefCell;
+use std::rc::Rc;
 use log::error;
 use std::backtrace::Backtrace;
 use std::fs::File;
@@ -31,11 +32,92 @@ use crate::{
     process_state::*,
     sys::*,
     binder_object::*,
+    observer,
 };
 
+// A thread has a ThreadState for each ProcessState(binder device) it talks to.
+type ThreadStates = Vec<(*const ProcessState, Rc<RefCell<ThreadState>>)>;
+
 thread_local! {
-    static THREAD_STATE: RefCell<ThreadState> = RefCell::new(ThreadState::new());
+    static THREAD_STATES: RefCell<ThreadStates> = const { RefCell::new(Vec::new()) };
+    // The stack of ProcessStates entered by this thread. The top is the current ProcessState.
+    static PROCESS_STACK: RefCell<Vec<Arc<ProcessState>>> = const { RefCell::new(Vec::new()) };
     static BINDER_DEREFS: RefCell<BinderDerefs> = RefCell::new(BinderDerefs::new());
+    // The caller of the oneway transaction whose handler is running on this thread.
+    static ONEWAY_CALLER: RefCell<Option<CallingContext>> = const { RefCell::new(None) };
+}
+
+/// Get the ProcessState which the current thread is talking to.
+/// It is the default ProcessState unless the thread entered another one.
+pub(crate) fn current_process() -> Arc<ProcessState> {
+    try_current_process().expect("ProcessState is not initialized!")
+}
+
+// Same as current_process(), but returns None if the default ProcessState is not initialized.
+fn try_current_process() -> Option<Arc<ProcessState>> {
+    PROCESS_STACK.with(|stack| stack.borrow().last().cloned())
+        .or_else(ProcessState::try_default_instance)
+}
+
+/// Run `f` with `process` as the current ProcessState of this thread.
+pub(crate) fn enter<R>(process: &Arc<ProcessState>, f: impl FnOnce() -> R) -> R {
+    struct Guard;
+    impl Drop for Guard {
+        fn drop(&mut self) {
+            PROCESS_STACK.with(|stack| stack.borrow_mut().pop());
+            release_shut_down_thread_states();
+        }
+    }
+
+    PROCESS_STACK.with(|stack| stack.borrow_mut().push(process.clone()));
+    let _guard = Guard;
+    f()
+}
+
+// A ThreadState keeps the settings of the thread like the work source and the call
+// restriction, so it lives as long as the thread. But the ThreadState of a ProcessState
+// built by ProcessState::builder() is dropped once the ProcessState is shut down, not to
+// keep it alive by the thread-local. The kernel references are already released by
+// shutdown(), so the pending commands of the ThreadState are dropped with it.
+pub(crate) fn release_shut_down_thread_states() {
+    let released: Vec<_> = THREAD_STATES.with(|states| {
+        let mut states = states.borrow_mut();
+        let mut released = Vec::new();
+        let mut index = 0;
+        while index < states.len() {
+            let (ptr, state) = &states[index];
+            let entered = PROCESS_STACK.with(|stack| {
+                stack.borrow().iter().any(|process| Arc::as_ptr(process) == *ptr)
+            });
+            // It is still borrowed if the thread is unwinding from a panic.
+            let shut_down = !entered && state.try_borrow().is_ok_and(|state| state.process.is_shut_down());
+            if shut_down {
+                released.push(states.remove(index));
+            } else {
+                index += 1;
+            }
+        }
+        released
+    });
+    // Dropping the last reference of a ProcessState may use the thread-local again.
+    drop(released);
+}
+
+fn with_thread_state<R>(f: impl FnOnce(&RefCell<ThreadState>) -> R) -> R {
+    let process = current_process();
+    let thread_state = THREAD_STATES.with(|states| {
+        let mut states = states.borrow_mut();
+        let key = Arc::as_ptr(&process);
+        match states.iter().find(|(ptr, _)| *ptr == key) {
+            Some((_, state)) => state.clone(),
+            None => {
+                let state = Rc::new(RefCell::new(ThreadState::new(process)));
+                states.push((key, state.clone()));
+                state
+            }
+        }
+    });
+    f(&thread_state)
 }
 
 const RETURN_STRINGS: [&str; 21] =
@@ -105,29 +187,26 @@ fn command_to_str(cmd: std::os::raw::c_uint) -> &'static str {
 }
 
 const WORK_SOURCE_PROPAGATED_BIT_INDEX: i64 = 32;
-pub(crate) const UNSET_WORK_SOURCE: i32 = -1;
+/// The work source uid which means that no work source is set.
+pub const UNSET_WORK_SOURCE: i32 = -1;
 
 #[derive(Debug, Clone, Copy)]
 struct TransactionState {
     calling_pid: binder::pid_t,
-    _calling_sid: *const u8,
-    _calling_uid: binder::uid_t,
+    calling_sid: *const u8,
+    calling_uid: binder::uid_t,
     // strict_mode_policy: i32,
     last_transaction_binder_flags: u32,
-    work_source: binder::uid_t,
-    propagate_work_source: bool,
 }
 
 impl TransactionState {
     fn from_transaction_data(data: &binder::binder_transaction_data_secctx) -> Self {
         TransactionState {
             calling_pid: data.transaction_data.sender_pid,
-            _calling_sid: data.secctx as _,
-            _calling_uid: data.transaction_data.sender_euid,
+            calling_sid: data.secctx as _,
+            calling_uid: data.transaction_data.sender_euid,
             // strict_mode_policy: 0,
             last_transaction_binder_flags: data.transaction_data.flags,
-            work_source: 0,
-            propagate_work_source: false,
         }
     }
 }
@@ -190,10 +269,13 @@ pub(crate) struct ThreadState {
     is_flushing: bool,
     call_restriction: CallRestriction,
     driver: Arc<File>,
+    process: Arc<ProcessState>,
+    work_source: binder::uid_t,
+    propagate_work_source: bool,
 }
 
 impl ThreadState {
-    fn new() -> Self {
+    fn new(process: Arc<ProcessState>) -> Self {
         ThreadState {
             in_parcel: Parcel::new(),
             out_parcel: Parcel::new(),
@@ -201,8 +283,11 @@ impl ThreadState {
             strict_mode_policy: 0,
             is_looper: false,
             is_flushing: false,
-            call_restriction: ProcessState::as_self().call_restriction(),
-            driver: ProcessState::as_self().driver(),
+            call_restriction: process.call_restriction(),
+            driver: process.driver(),
+            process,
+            work_source: UNSET_WORK_SOURCE as _,
+            propagate_work_source: false,
         }
     }
 
@@ -226,36 +311,30 @@ impl ThreadState {
     }
 
     fn clear_propagate_work_source(&mut self) {
-        if let Some(mut state) = self.transaction {
-            state.propagate_work_source = false;
-        }
+        self.propagate_work_source = false;
     }
 
-    fn clear_calling_work_source(&mut self) {
-        self.set_calling_work_source_uid(UNSET_WORK_SOURCE as _);
+    fn clear_calling_work_source(&mut self) -> i64 {
+        self.set_calling_work_source_uid(UNSET_WORK_SOURCE as _)
     }
 
     fn set_calling_work_source_uid(&mut self, uid: binder::uid_t) -> i64 {
         let token = self.set_calling_work_source_uid_without_propagation(uid);
-        if let Some(mut state) = self.transaction {
-            state.propagate_work_source = true;
-        }
+        self.propagate_work_source = true;
         token
     }
 
-    pub(crate) fn set_calling_work_source_uid_without_propagation(&mut self, uid: binder::uid_t) -> i64 {
-        match self.transaction {
-            Some(mut state) => {
-                let propagated_bit = (state.propagate_work_source as i64) << WORK_SOURCE_PROPAGATED_BIT_INDEX;
-                let token = propagated_bit | (state.work_source as i64);
-                state.work_source = uid;
+    fn set_calling_work_source_uid_without_propagation(&mut self, uid: binder::uid_t) -> i64 {
+        let propagated_bit = (self.propagate_work_source as i64) << WORK_SOURCE_PROPAGATED_BIT_INDEX;
+        let token = propagated_bit | (self.work_source as i64);
+        self.work_source = uid;
 
-                token
-            }
-            None => {
-                0
-            }
-        }
+        token
+    }
+
+    fn restore_calling_work_source(&mut self, token: i64) {
+        self.set_calling_work_source_uid_without_propagation(token as u32 as _);
+        self.propagate_work_source = ((token >> WORK_SOURCE_PROPAGATED_BIT_INDEX) & 1) != 0;
     }
 
     fn write_transaction_data(&mut self, cmd: u32, mut flags: u32, handle: u32, code: u32, data: &Parcel, status: &i32) -> Result<()> {
@@ -307,47 +386,100 @@ impl ThreadState {
             }
         };
 
-        self.out_parcel.write::<u32>(&cmd)?;
-        self.out_parcel.write_aligned(&tr);
+        // Send the scatter-gather buffers of the parcel with the SG variant of the command.
+        let buffers_size = data.sg_buffers_size();
+        if buffers_size > 0 && *status == StatusCode::Ok.into() {
+            let cmd = match cmd {
+                binder::BC_TRANSACTION => binder::BC_TRANSACTION_SG,
+                binder::BC_REPLY => binder::BC_REPLY_SG,
+                _ => cmd,
+            };
+            let tr_sg = binder::binder_transaction_data_sg {
+                transaction_data: tr,
+                buffers_size: buffers_size as _,
+            };
+            self.out_parcel.write::<u32>(&cmd)?;
+            self.out_parcel.write_aligned(&tr_sg);
+        } else {
+            self.out_parcel.write::<u32>(&cmd)?;
+            self.out_parcel.write_aligned(&tr);
+        }
 
         Ok(())
     }
 }
 
 pub(crate) fn set_call_restriction(call_restriction: CallRestriction) {
-    THREAD_STATE.with(|thread_state| {
+    with_thread_state(|thread_state| {
         thread_state.borrow_mut().call_restriction = call_restriction;
     })
 }
 
 pub(crate) fn call_restriction() -> CallRestriction {
-    THREAD_STATE.with(|thread_state| {
+    with_thread_state(|thread_state| {
         thread_state.borrow().call_restriction
     })
 }
 
 pub(crate) fn strict_mode_policy() -> i32 {
-    THREAD_STATE.with(|thread_state| {
+    with_thread_state(|thread_state| {
         thread_state.borrow().strict_mode_policy
     })
 }
 
-pub(crate) fn should_propagate_work_source() -> bool {
-    THREAD_STATE.with(|thread_state| {
-        thread_state.borrow().transaction.map_or(false, |state| state.propagate_work_source)
+/// Whether the work source uid is propagated to the outgoing transactions of the current thread.
+pub fn should_propagate_work_source() -> bool {
+    with_thread_state(|thread_state| {
+        thread_state.borrow().propagate_work_source
+    })
+}
+
+/// Get the work source uid of the current thread.
+///
+/// While handling a transaction, it is the work source uid sent by the caller.
+/// The work source is the uid of the process the work is actually done for,
+/// which can be different from the calling uid.
+/// It is `UNSET_WORK_SOURCE as uid_t` if no work source is set.
+pub fn calling_work_source_uid() -> binder::uid_t {
+    with_thread_state(|thread_state| {
+        thread_state.borrow().work_source
+    })
+}
+
+/// Set the work source uid which is propagated to the outgoing transactions of the current thread.
+/// The returned token must be passed to restore_calling_work_source() to restore
+/// the previous work source.
+pub fn set_calling_work_source_uid(uid: binder::uid_t) -> i64 {
+    with_thread_state(|thread_state| {
+        thread_state.borrow_mut().set_calling_work_source_uid(uid)
+    })
+}
+
+/// Clear the work source uid of the current thread. UNSET_WORK_SOURCE is propagated
+/// to the outgoing transactions.
+/// The returned token must be passed to restore_calling_work_source() to restore
+/// the previous work source.
+pub fn clear_calling_work_source() -> i64 {
+    with_thread_state(|thread_state| {
+        thread_state.borrow_mut().clear_calling_work_source()
     })
 }
 
-pub(crate) fn calling_work_source_uid() -> binder::uid_t {
-    THREAD_STATE.with(|thread_state| {
-        thread_state.borrow().transaction.map_or(0, |state| state.work_source)
+/// Restore the work source of the current thread with the token returned by
+/// set_calling_work_source_uid() or clear_calling_work_source().
+pub fn restore_calling_work_source(token: i64) {
+    with_thread_state(|thread_state| {
+        thread_state.borrow_mut().restore_calling_work_source(token)
     })
 }
 
 
-pub(crate) fn _setup_polling() -> Result<()> {
-    THREAD_STATE.with(|thread_state| -> Result<()> {
-        thread_state.borrow_mut().out_parcel.write::<u32>(&binder::BC_ENTER_LOOPER)
+pub(crate) fn setup_polling() -> Result<()> {
+    with_thread_state(|thread_state| -> Result<()> {
+        let mut thread_state = thread_state.borrow_mut();
+        thread_state.out_parcel.write::<u32>(&binder::BC_ENTER_LOOPER)?;
+        thread_state.is_looper = true;
+        Ok(())
     })?;
     flush_commands()?;
     Ok(())
@@ -360,7 +492,7 @@ enum UntilResponse {
 }
 
 fn wait_for_response(until: UntilResponse) -> Result<Option<Parcel>> {
-    THREAD_STATE.with(|thread_state| -> Result<Option<Parcel>> {
+    with_thread_state(|thread_state| -> Result<Option<Parcel>> {
         loop {
             talk_with_driver(true)?;
 
@@ -396,7 +528,7 @@ fn wait_for_response(until: UntilResponse) -> Result<Option<Parcel>> {
                 binder::BR_FROZEN_REPLY => {
                     log::error!("Received FROZEN_REPLY transaction reply for pid {}",
                         thread_state.borrow().transaction.map_or(0, |state| state.calling_pid));
-                    return Err(StatusCode::FailedTransaction);
+                    return Err(StatusCode::FrozenObject);
                 },
                 binder::BR_ACQUIRE_RESULT => {
                     let result = thread_state.borrow_mut().in_parcel.read::<i32>()?;
@@ -455,7 +587,7 @@ fn wait_for_response(until: UntilResponse) -> Result<Option<Parcel>> {
 fn execute_command(cmd: i32) -> Result<()> {
     let cmd: std::os::raw::c_uint = cmd as _;
 
-    THREAD_STATE.with(|thread_state| -> Result<()> {
+    with_thread_state(|thread_state| -> Result<()> {
         match cmd {
             binder::BR_ERROR => {
                 let other: StatusCode = thread_state.borrow_mut().in_parcel.read::<i32>()?.into();
@@ -490,28 +622,39 @@ fn execute_command(cmd: i32) -> Result<()> {
                 // const void* origServingStackPointer = mServingStackPointer;
                 // mServingStackPointer = &origServingStackPointer; // anything on the stack
 
-                let transaction_old = {
+                let (transaction_old, work_source_old, propagate_work_source_old) = {
                     let mut thread_state = thread_state.borrow_mut();
                     let transaction_old = thread_state.transaction;
+                    let work_source_old = thread_state.work_source;
+                    let propagate_work_source_old = thread_state.propagate_work_source;
 
                     thread_state.clear_calling_work_source();
                     thread_state.clear_propagate_work_source();
 
                     thread_state.transaction = Some(TransactionState::from_transaction_data(&tr_secctx));
 
-                    transaction_old
+                    (transaction_old, work_source_old, propagate_work_source_old)
                 };
 
                 let mut reply = Parcel::new();
 
+                let code = tr_secctx.transaction_data.code;
+                let flags = tr_secctx.transaction_data.flags;
+                let transact = |binder: &SIBinder, reader: &mut Parcel, reply: &mut Parcel| {
+                    let observation = observer::Observation::incoming(binder.descriptor(), code, flags);
+                    let result = binder.as_transactable().expect("Transactable is None.")
+                        .transact(code, reader, reply);
+                    observation.finish(reader.data_size(), reply.data_size(), result);
+                    result
+                };
+
                 let result = {
                     let target_ptr = unsafe { tr_secctx.transaction_data.target.ptr };
                     // reader.set_data_position(0);
                     if target_ptr != 0 {
                         let strong = raw_pointer_to_strong_binder((target_ptr, tr_secctx.transaction_data.cookie));
                         if strong.attempt_increase() {
-                            let result = strong.as_transactable().expect("Transactable is None.")
-                                .transact(tr_secctx.transaction_data.code, &mut reader, &mut reply);
+                            let result = transact(&strong, &mut reader, &mut reply);
                             strong.decrease()?;
 
                             result
@@ -520,11 +663,11 @@ fn execute_command(cmd: i32) -> Result<()> {
                             Err(StatusCode::UnknownTransaction)
                         }
                     } else {
-                        let context = ProcessState::as_self().context_manager().expect("Transactable is None.");
-                        context.as_transactable().expect("Transactable is None.").transact(tr_secctx.transaction_data.code, &mut reader, &mut reply)
+                        let process = thread_state.borrow().process.clone();
+                        let context = process.context_manager().expect("Transactable is None.");
+                        transact(&context, &mut reader, &mut reply)
                     }
                 };
-                let flags = tr_secctx.transaction_data.flags;
                 if (flags & transaction_flags_TF_ONE_WAY) == 0 {
                     let flags = flags & transaction_flags_TF_CLEAR_BUF;
                     let status: i32 = match result {
@@ -546,7 +689,12 @@ fn execute_command(cmd: i32) -> Result<()> {
                     log::error!("{}", log);
                 }
 
-                thread_state.borrow_mut().transaction = transaction_old;
+                {
+                    let mut thread_state = thread_state.borrow_mut();
+                    thread_state.transaction = transaction_old;
+                    thread_state.work_source = work_source_old;
+                    thread_state.propagate_work_source = propagate_work_source_old;
+                }
             }
 
             binder::BR_INCREFS => {
@@ -608,7 +756,8 @@ fn execute_command(cmd: i32) -> Result<()> {
             }
             binder::BR_NOOP => {}
             binder::BR_SPAWN_LOOPER => {
-                ProcessState::as_self().spawn_pooled_thread(false);
+                let process = thread_state.borrow().process.clone();
+                process.spawn_pooled_thread(false);
             }
             binder::BR_FINISHED => {
                 return Err(StatusCode::TimedOut);
@@ -619,7 +768,8 @@ fn execute_command(cmd: i32) -> Result<()> {
                     state.in_parcel.read::<binder::binder_uintptr_t>()?
                 };
 
-                ProcessState::as_self().send_obituary_for_handle(handle as _)?;
+                let process = thread_state.borrow().process.clone();
+                process.send_obituary_for_handle(handle as _)?;
 
                 {
                     let mut state = thread_state.borrow_mut();
@@ -643,7 +793,7 @@ fn execute_command(cmd: i32) -> Result<()> {
 
 
 fn talk_with_driver(do_receive: bool) -> Result<()> {
-    THREAD_STATE.with(|thread_state| -> Result<()> {
+    with_thread_state(|thread_state| -> Result<()> {
         let mut bwr = {
             let mut thread_state = thread_state.borrow_mut();
             let need_read = thread_state.in_parcel.is_empty();
@@ -730,7 +880,7 @@ fn talk_with_driver(do_receive: bool) -> Result<()> {
 fn get_and_execute_command() -> Result<()> {
     talk_with_driver(true)?;
 
-    let cmd = THREAD_STATE.with(|thread_state| -> Result<i32> {
+    let cmd = with_thread_state(|thread_state| -> Result<i32> {
         thread_state.borrow_mut().in_parcel.read::<i32>()
     })?;
     execute_command(cmd)?;
@@ -741,7 +891,7 @@ fn get_and_execute_command() -> Result<()> {
 pub(crate) fn flush_commands() -> Result<()> {
     talk_with_driver(false)?;
 
-    THREAD_STATE.with(|thread_state| -> Result<()> {
+    with_thread_state(|thread_state| -> Result<()> {
         if thread_state.borrow().out_parcel.data_size() > 0 {
             talk_with_driver(false)?;
         }
@@ -756,7 +906,7 @@ pub(crate) fn flush_commands() -> Result<()> {
 
 pub(crate) fn attempt_inc_strong_handle(handle: u32) -> Result<()> {
     log::trace!("attempt_inc_strong_handle: {handle}");
-    THREAD_STATE.with(|thread_state| -> Result<()> {
+    with_thread_state(|thread_state| -> Result<()> {
         let mut state = thread_state.borrow_mut();
 
         state.out_parcel.write::<u32>(&(binder::BC_ATTEMPT_ACQUIRE))?;
@@ -768,7 +918,7 @@ pub(crate) fn attempt_inc_strong_handle(handle: u32) -> Result<()> {
 
 pub(crate) fn inc_strong_handle(handle: u32, proxy: SIBinder) -> Result<()> {
     log::trace!("inc_strong_handle: {handle}");
-    THREAD_STATE.with(|thread_state| -> Result<()> {
+    with_thread_state(|thread_state| -> Result<()> {
         {
             let mut state = thread_state.borrow_mut();
 
@@ -788,7 +938,7 @@ pub(crate) fn inc_strong_handle(handle: u32, proxy: SIBinder) -> Result<()> {
 
 pub(crate) fn dec_strong_handle(handle: u32) -> Result<()> {
     log::trace!("dec_strong_handle: {handle}");
-    THREAD_STATE.with(|thread_state| -> Result<()> {
+    with_thread_state(|thread_state| -> Result<()> {
         {
             let mut state = thread_state.borrow_mut();
 
@@ -804,7 +954,7 @@ pub(crate) fn dec_strong_handle(handle: u32) -> Result<()> {
 
 pub(crate) fn inc_weak_handle(handle: u32, weak: &WIBinder) -> Result<()>{
     log::trace!("inc_weak_handle: {handle}");
-    THREAD_STATE.with(|thread_state| -> Result<()> {
+    with_thread_state(|thread_state| -> Result<()> {
         {
             let mut state = thread_state.borrow_mut();
 
@@ -825,7 +975,7 @@ pub(crate) fn inc_weak_handle(handle: u32, weak: &WIBinder) -> Result<()>{
 
 pub(crate) fn dec_weak_handle(handle: u32) -> Result<()> {
     log::trace!("dec_weak_handle: {handle}");
-    THREAD_STATE.with(|thread_state| -> Result<()> {
+    with_thread_state(|thread_state| -> Result<()> {
         {
             let mut state = thread_state.borrow_mut();
 
@@ -841,7 +991,7 @@ pub(crate) fn dec_weak_handle(handle: u32) -> Result<()> {
 
 
 pub(crate) fn flash_if_needed() -> Result<bool> {
-    THREAD_STATE.with(|thread_state| -> Result<bool> {
+    with_thread_state(|thread_state| -> Result<bool> {
         {
             let thread_state = thread_state.borrow();
             if thread_state.is_looper || thread_state.is_flushing {
@@ -857,23 +1007,41 @@ pub(crate) fn flash_if_needed() -> Result<bool> {
     })
 }
 
-pub(crate) fn _handle_commands() -> Result<()> {
-    while {
+pub(crate) fn exit_polling() -> Result<()> {
+    with_thread_state(|thread_state| -> Result<()> {
+        let mut thread_state = thread_state.borrow_mut();
+        thread_state.out_parcel.write::<u32>(&binder::BC_EXIT_LOOPER)?;
+        thread_state.is_looper = false;
+        Ok(())
+    })?;
+    flush_commands()
+}
+
+pub(crate) fn handle_polled_commands() -> Result<()> {
+    // Execute all commands read from the driver at once.
+    loop {
         get_and_execute_command()?;
 
-        THREAD_STATE.with(|thread_state| -> bool {
-            !thread_state.borrow().in_parcel.is_empty()
-        })
-    } {
-        flush_commands()?;
+        if with_thread_state(|thread_state| thread_state.borrow().in_parcel.is_empty()) {
+            break;
+        }
     }
-    Ok(())
+
+    with_thread_state(|thread_state| -> Result<()> {
+        if thread_state.borrow_mut().is_process_pending_derefs() {
+            BINDER_DEREFS.with(|binder_derefs| -> Result<()> {
+                binder_derefs.borrow_mut().process_pending_derefs()
+            })?;
+        }
+        Ok(())
+    })?;
+    flush_commands()
 }
 
 pub fn check_interface(reader: &mut Parcel, descriptor: &str) -> Result<bool> {
     let mut strict_policy: i32 = reader.read()?;
 
-    let header = THREAD_STATE.with(|thread_state| -> Result<u32> {
+    let header = with_thread_state(|thread_state| -> Result<u32> {
         let mut thread_state = thread_state.borrow_mut();
 
         if (thread_state.last_transaction_binder_flags() & FLAG_ONEWAY) != 0 {
@@ -902,12 +1070,29 @@ pub fn check_interface(reader: &mut Parcel, descriptor: &str) -> Result<bool> {
     }
 }
 
+// Keep the memory map of a ProcessState while the thread waits for a reply in its buffer.
+struct BufferHold(Arc<ProcessState>);
+
+impl BufferHold {
+    fn new(process: Arc<ProcessState>) -> Result<Self> {
+        process.try_acquire_buffer()?;
+        Ok(Self(process))
+    }
+}
+
+impl Drop for BufferHold {
+    fn drop(&mut self) {
+        self.0.release_buffer();
+    }
+}
+
 pub(crate) fn transact(handle: u32, code: u32, data: &Parcel, mut flags: u32) -> Result<Option<Parcel>> {
     let mut reply: Option<Parcel> = None;
+    let _hold = BufferHold::new(current_process())?;
 
     flags |= transaction_flags_TF_ACCEPT_FDS;
 
-    let call_restriction = THREAD_STATE.with(|thread_state| -> Result<CallRestriction> {
+    let call_restriction = with_thread_state(|thread_state| -> Result<CallRestriction> {
         let mut thread_state = thread_state.borrow_mut();
         thread_state.write_transaction_data(binder::BC_TRANSACTION, flags, handle, code, data, &0)?;
         Ok(thread_state.call_restriction)
@@ -938,7 +1123,7 @@ fn free_buffer(parcel: Option<&Parcel>, data: binder_uintptr_t, _: usize, _ : bi
         parcel.close_file_descriptors()
     }
 
-    THREAD_STATE.with(|thread_state| -> Result<()> {
+    with_thread_state(|thread_state| -> Result<()> {
         let mut thread_state = thread_state.borrow_mut();
         thread_state.out_parcel.write::<u32>(&binder::BC_FREE_BUFFER)?;
         thread_state.out_parcel.write::<binder_uintptr_t>(&data)?;
@@ -964,12 +1149,19 @@ pub(crate) fn ping_binder(handle: u32) -> Result<()> {
     Ok(())
 }
 
+pub(crate) fn is_looper() -> bool {
+    with_thread_state(|thread_state| {
+        thread_state.borrow().is_looper
+    })
+}
+
 pub(crate) fn join_thread_pool(is_main: bool) -> Result<()> {
-    THREAD_STATE.with(|thread_state| -> Result<()> {
+    with_thread_state(|thread_state| -> Result<()> {
         log::debug!("**** THREAD {:?} (PID {}) IS JOINING THE THREAD POOL",
             std::thread::current().id(), std::process::id());
 
-        ProcessState::as_self().current_threads.fetch_add(1, Ordering::SeqCst);
+        let process = thread_state.borrow().process.clone();
+        process.current_threads.fetch_add(1, Ordering::SeqCst);
 
         let looper = if is_main {
             binder::BC_ENTER_LOOPER
@@ -986,6 +1178,11 @@ pub(crate) fn join_thread_pool(is_main: bool) -> Result<()> {
         let result;
 
         loop {
+            // Leave the thread pool only after all received commands are handled.
+            if process.is_shutting_down() && thread_state.borrow().in_parcel.is_empty() {
+                result = StatusCode::Ok;
+                break;
+            }
             if thread_state.borrow_mut().is_process_pending_derefs() {
                 BINDER_DEREFS.with(|binder_derefs| -> Result<()> {
                     binder_derefs.borrow_mut().process_pending_derefs()
@@ -1018,14 +1215,20 @@ pub(crate) fn join_thread_pool(is_main: bool) -> Result<()> {
         }
 
         talk_with_driver(false)?;
-        ProcessState::as_self().current_threads.fetch_sub(1, Ordering::SeqCst);
+        if process.is_shutting_down() {
+            let driver_fd = thread_state.borrow().driver.as_raw_fd();
+            if let Err(e) = unsafe { binder::thread_exit(driver_fd, &0) } {
+                log::warn!("Binder ioctl to exit the thread failed: {}", e);
+            }
+        }
+        process.current_threads.fetch_sub(1, Ordering::SeqCst);
         Ok(())
     })
 }
 
 pub(crate) fn request_death_notification(handle: u32) -> Result<()> {
     log::trace!("request_death_notification: {handle}");
-    THREAD_STATE.with(|thread_state| -> Result<()> {
+    with_thread_state(|thread_state| -> Result<()> {
         {
             let mut state = thread_state.borrow_mut();
 
@@ -1041,7 +1244,7 @@ pub(crate) fn request_death_notification(handle: u32) -> Result<()> {
 
 pub(crate) fn clear_death_notification(handle: u32) -> Result<()> {
     log::trace!("clear_death_notification: {handle}");
-    THREAD_STATE.with(|thread_state| -> Result<()> {
+    with_thread_state(|thread_state| -> Result<()> {
         {
             let mut state = thread_state.borrow_mut();
 
@@ -1055,30 +1258,168 @@ pub(crate) fn clear_death_notification(handle: u32) -> Result<()> {
     })
 }
 
+/// Identity of the caller of the transaction being handled by the current thread.
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct CallingContext {
+    /// Process id of the caller.
     pub pid: binder::pid_t,
+    /// Effective user id of the caller.
     pub uid: binder::uid_t,
-    pub sid: *const u8,
+    /// Security context of the caller. It is delivered only if the receiving binder
+    /// requested it.
+    pub sid: Option<String>,
 }
 
-pub(crate) fn _get_calling_context() -> Result<CallingContext> {
-    THREAD_STATE.with(|thread_state| -> Result<CallingContext> {
-        let thread_state = thread_state.borrow();
-        let transaction = thread_state.transaction.as_ref().ok_or(StatusCode::Unknown)?;
-        let calling_pid = transaction.calling_pid;
-        let calling_uid = transaction._calling_uid;
-        let calling_sid = transaction._calling_sid;
-
-        Ok(CallingContext {
-            pid: calling_pid,
-            uid: calling_uid,
-            sid: calling_sid,
-        })
+/// Get the identity of the caller of the transaction being handled by the current thread.
+///
+/// If the thread is not handling a transaction or the identity was cleared by
+/// clear_calling_identity(), the identity of the current process is returned.
+/// It is also returned if ProcessState is not initialized.
+///
+/// The handler of a oneway transaction of an async service runs after the transaction
+/// is finished, but it gets the caller of the transaction from this function.
+pub fn calling_context() -> CallingContext {
+    if let Some(caller) = ONEWAY_CALLER.with(|caller| caller.borrow().clone()) {
+        return caller;
+    }
+    if try_current_process().is_none() {
+        return local_calling_context();
+    }
+
+    with_thread_state(|thread_state| {
+        match thread_state.borrow().transaction {
+            Some(transaction) => {
+                let sid = if transaction.calling_sid.is_null() {
+                    None
+                } else {
+                    // The security context is a null-terminated string in the transaction buffer,
+                    // which is valid while the transaction is handled.
+                    let sid = unsafe { std::ffi::CStr::from_ptr(transaction.calling_sid as _) };
+                    Some(sid.to_string_lossy().into_owned())
+                };
+                CallingContext {
+                    pid: transaction.calling_pid,
+                    uid: transaction.calling_uid,
+                    sid,
+                }
+            }
+            None => local_calling_context(),
+        }
+    })
+}
+
+// Run `f` as the handler of a oneway transaction from `caller`.
+pub(crate) fn with_oneway_caller<R>(caller: &CallingContext, f: impl FnOnce() -> R) -> R {
+    struct Restore(Option<CallingContext>);
+
+    impl Drop for Restore {
+        fn drop(&mut self) {
+            ONEWAY_CALLER.with(|caller| *caller.borrow_mut() = self.0.take());
+        }
+    }
+
+    let _restore = Restore(ONEWAY_CALLER.with(|current| current.borrow_mut().replace(caller.clone())));
+    f()
+}
+
+fn local_calling_context() -> CallingContext {
+    CallingContext {
+        pid: std::process::id() as _,
+        uid: nix::unistd::geteuid().as_raw(),
+        sid: None,
+    }
+}
+
+/// Reset the identity of the incoming transaction on the current thread to
+/// the identity of the current process.
+///
+/// It is useful to call into other services with the permissions of the current process.
+/// The returned token must be passed to restore_calling_identity().
+pub fn clear_calling_identity() -> i64 {
+    with_thread_state(|thread_state| {
+        let mut thread_state = thread_state.borrow_mut();
+        match thread_state.transaction.as_mut() {
+            Some(transaction) => {
+                let token = ((transaction.calling_uid as i64) << 32) | (transaction.calling_pid as u32 as i64);
+                transaction.calling_pid = std::process::id() as _;
+                transaction.calling_uid = nix::unistd::geteuid().as_raw();
+                transaction.calling_sid = std::ptr::null();
+                token
+            }
+            None => ((nix::unistd::geteuid().as_raw() as i64) << 32) | (std::process::id() as i64),
+        }
+    })
+}
+
+/// Restore the identity of the incoming transaction on the current thread
+/// with the token returned by clear_calling_identity().
+///
+/// The security context isn't restored because the token doesn't have it.
+pub fn restore_calling_identity(token: i64) {
+    with_thread_state(|thread_state| {
+        if let Some(transaction) = thread_state.borrow_mut().transaction.as_mut() {
+            transaction.calling_pid = token as u32 as _;
+            transaction.calling_uid = (token >> 32) as _;
+            transaction.calling_sid = std::ptr::null();
+        }
     })
 }
 
 pub fn is_handling_transaction() -> bool {
-    THREAD_STATE.with(|thread_state| {
+    with_thread_state(|thread_state| {
         thread_state.borrow().transaction.is_some()
     })
-}
\ No newline at end of file
+}
+
+pub(crate) fn last_transaction_binder_flags() -> u32 {
+    with_thread_state(|thread_state| {
+        thread_state.borrow().last_transaction_binder_flags()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn build_process() -> Arc<ProcessState> {
+        ProcessState::builder()
+            .driver(crate::DEFAULT_BINDER_PATH)
+            .max_threads(0)
+            .build()
+            .unwrap()
+    }
+
+    fn has_thread_state(process: &Arc<ProcessState>) -> bool {
+        THREAD_STATES.with(|states| states.borrow().iter().any(|(ptr, _)| *ptr == Arc::as_ptr(process)))
+    }
+
+    #[test]
+    fn test_enter_current_process() {
+        let first = build_process();
+        let second = build_process();
+
+        enter(&first, || {
+            assert!(Arc::ptr_eq(&current_process(), &first));
+            enter(&second, || assert!(Arc::ptr_eq(&current_process(), &second)));
+            assert!(Arc::ptr_eq(&current_process(), &first));
+            enter(&first, || assert!(Arc::ptr_eq(&current_process(), &first)));
+        });
+    }
+
+    #[test]
+    fn test_release_thread_state() {
+        let process = build_process();
+
+        // The settings of the thread are kept across enter().
+        enter(&process, || set_calling_work_source_uid(1234));
+        assert!(has_thread_state(&process));
+        assert_eq!(enter(&process, calling_work_source_uid), 1234);
+
+        // The thread-local doesn't keep the ProcessState alive after shutdown().
+        process.shutdown(std::time::Duration::from_secs(1)).unwrap();
+        assert!(!has_thread_state(&process));
+        let weak = Arc::downgrade(&process);
+        drop(process);
+        assert!(weak.upgrade().is_none());
+    }
+}